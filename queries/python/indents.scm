@@ -0,0 +1,29 @@
+; Compound statements indent their body. Their `elif`, `else`, `except` and `finally` clauses are
+; children of the statement, so they share its indentation.
+[
+  (function_definition)
+  (class_definition)
+  (if_statement)
+  (for_statement)
+  (while_statement)
+  (with_statement)
+  (try_statement)
+  (argument_list)
+  (parameters)
+  (list)
+  (dictionary)
+  (set)
+  (tuple)
+  (parenthesized_expression)
+] @indent
+
+[
+  ")"
+  "]"
+  "}"
+] @outdent
+
+(elif_clause "elif" @outdent)
+(else_clause "else" @outdent)
+(except_clause "except" @outdent)
+(finally_clause "finally" @outdent)
@@ -0,0 +1,16 @@
+(class_definition
+  name: (identifier) @name) @definition.class
+
+(class_definition
+  body: (block
+    (function_definition
+      name: (identifier) @name) @definition.method))
+
+(class_definition
+  body: (block
+    (decorated_definition
+      (function_definition
+        name: (identifier) @name) @definition.method)))
+
+(function_definition
+  name: (identifier) @name) @definition.function
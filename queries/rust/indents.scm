@@ -0,0 +1,30 @@
+[
+  (block)
+  (match_block)
+  (declaration_list)
+  (field_declaration_list)
+  (enum_variant_list)
+  (field_initializer_list)
+  (use_list)
+  (arguments)
+  (parameters)
+  (type_arguments)
+  (type_parameters)
+  (array_expression)
+  (tuple_expression)
+  (tuple_pattern)
+  (struct_pattern)
+  (token_tree)
+  (macro_definition)
+  (where_clause)
+] @indent
+
+; An opening brace that is not closed yet.
+(ERROR "{") @indent
+
+[
+  "}"
+  ")"
+  "]"
+  ">"
+] @outdent
@@ -0,0 +1,44 @@
+; Scopes
+
+[
+  (function_item)
+  (closure_expression)
+  (block)
+  (for_expression)
+  (if_let_expression)
+  (while_let_expression)
+  (match_arm)
+] @local.scope
+
+; Definitions
+
+(parameter
+  pattern: (identifier) @local.definition)
+
+(closure_parameters
+  (identifier) @local.definition)
+
+(let_declaration
+  pattern: (identifier) @local.definition)
+
+(for_expression
+  pattern: (identifier) @local.definition)
+
+(if_let_expression
+  pattern: (identifier) @local.definition)
+
+(while_let_expression
+  pattern: (identifier) @local.definition)
+
+(tuple_pattern
+  (identifier) @local.definition)
+
+(mut_pattern
+  (identifier) @local.definition)
+
+(ref_pattern
+  (identifier) @local.definition)
+
+; References
+
+(identifier) @local.reference
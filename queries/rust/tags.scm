@@ -0,0 +1,34 @@
+(struct_item
+  name: (type_identifier) @name) @definition.struct
+
+(enum_item
+  name: (type_identifier) @name) @definition.enum
+
+(union_item
+  name: (type_identifier) @name) @definition.union
+
+(type_item
+  name: (type_identifier) @name) @definition.type
+
+(trait_item
+  name: (type_identifier) @name) @definition.trait
+
+; Implementations are named after their first line, such as `impl Display for Buffer`.
+(impl_item) @definition.impl
+
+(declaration_list
+  (function_item
+    name: (identifier) @name) @definition.method)
+
+(declaration_list
+  (function_signature_item
+    name: (identifier) @name) @definition.method)
+
+(function_item
+  name: (identifier) @name) @definition.function
+
+(mod_item
+  name: (identifier) @name) @definition.module
+
+(macro_definition
+  name: (identifier) @name) @definition.macro
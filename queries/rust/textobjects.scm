@@ -0,0 +1,45 @@
+(function_item
+  body: (block
+    "{" . (_)* @function.inside . "}")) @function.around
+
+(closure_expression
+  body: (_) @function.inside) @function.around
+
+(struct_item
+  body: (_) @class.inside) @class.around
+
+(enum_item
+  body: (_) @class.inside) @class.around
+
+(trait_item
+  body: (_
+    "{" . (_)* @class.inside . "}")) @class.around
+
+(impl_item
+  body: (_
+    "{" . (_)* @class.inside . "}")) @class.around
+
+(parameters
+  (_) @parameter.inside @parameter.around . ","? @parameter.around)
+
+(closure_parameters
+  (_) @parameter.inside @parameter.around . ","? @parameter.around)
+
+(type_parameters
+  (_) @parameter.inside @parameter.around . ","? @parameter.around)
+
+(arguments
+  (_) @parameter.inside @parameter.around . ","? @parameter.around)
+
+(type_arguments
+  (_) @parameter.inside @parameter.around . ","? @parameter.around)
+
+(line_comment) @comment.inside @comment.around
+
+(block_comment) @comment.inside @comment.around
+
+(block
+  "{" . (_)* @block.inside . "}") @block.around
+
+(declaration_list
+  "{" . (_)* @block.inside . "}") @block.around
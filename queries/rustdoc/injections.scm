@@ -0,0 +1,19 @@
+(fenced_code_block
+  (info_string
+    (language) @injection.language)
+  (code_fence_content) @injection.content)
+
+; Code blocks without a language, or with only rustdoc attributes, are Rust.
+(fenced_code_block
+  .
+  (fenced_code_block_delimiter)
+  .
+  (code_fence_content) @injection.content
+  (#set! injection.language "rust"))
+
+(fenced_code_block
+  (info_string
+    (language) @_attribute)
+  (code_fence_content) @injection.content
+  (#match? @_attribute "^(ignore|no_run|should_panic|compile_fail|edition[0-9]+)$")
+  (#set! injection.language "rust"))
@@ -1,26 +1,108 @@
+use crate::language::{language_by_name, language_for_path, Indent, LanguageConfig};
+use crate::rope_regex::RopeRegex;
+use crate::syntax::Syntax;
 use ropey::Rope;
-use std::ops::Range;
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tree_sitter::{InputEdit, Node, Point};
+use unicode_segmentation::UnicodeSegmentation;
 
 type Selection = Range<usize>;
 type Selections = Vec<Selection>;
 
+/// The pairs of characters that are closed as soon as they are opened in insert mode.
+const AUTO_PAIRS: [(char, char); 5] =
+    [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+/// A search for a character, as made by `f`, `t`, `F` and `T`.
+#[derive(Clone, Copy)]
+pub struct CharSearch {
+    pub target: char,
+    pub forward: bool,
+    /// Whether the search stops next to the character instead of on it.
+    pub till: bool,
+}
+
+/// The text and selections of a buffer before a change, restored by undoing it.
+struct Revision {
+    data: Rope,
+    selections: Selections,
+}
+
+/// A range of lines that can be collapsed into its first line.
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+/// A definition in the buffer, as listed by `:symbols` and the outline.
+#[derive(Clone)]
+pub struct Symbol {
+    pub kind: String,
+    pub name: String,
+    /// The character range of the whole definition.
+    pub range: Selection,
+    /// The character position of the definition's name.
+    pub name_start: usize,
+    /// How many other symbols enclose this one.
+    pub depth: usize,
+}
+
 pub struct Buffer {
+    pub path: Option<PathBuf>,
     pub data: Rope,
     pub selections: Selections,
+    pub language: Option<&'static LanguageConfig>,
+    pub syntax: Option<Syntax>,
+    /// Identifies the text of the buffer. It changes with every edit and is never shared with
+    /// another buffer, so views can keep what they derive from the text until it changes.
+    pub version: usize,
     scroll_col: Option<usize>,
+    /// The selections before and after each expansion to a syntax node, so that shrinking can go
+    /// back down the same nodes.
+    expansions: Vec<(Selections, Selections)>,
+    /// The folds of the buffer, ordered by their first line. Folds can be nested.
+    pub folds: Vec<Fold>,
+    pub indent: Indent,
+    /// The revisions undo goes back to, oldest first.
+    undo: Vec<Revision>,
+    /// The revisions redo goes forward to, the next one last.
+    redo: Vec<Revision>,
+    /// The revision before the transaction in progress, whose changes are undone together.
+    transaction: Option<Revision>,
 }
 
 impl Buffer {
-    pub fn new(data: String) -> Buffer {
+    pub fn new(data: String, language: Option<&'static LanguageConfig>) -> Buffer {
+        let data = Rope::from(data);
+        let syntax = language.map(|language| Syntax::new(language, &data));
+        let folds = compute_folds(&data, syntax.as_ref());
         return Buffer {
-            data: Rope::from(data),
+            path: None,
+            syntax,
+            version: next_version(),
+            language,
+            data,
             selections: vec![0..1],
             scroll_col: None,
+            expansions: vec![],
+            folds,
+            indent: language.map(|language| language.indent).unwrap_or_default(),
+            undo: vec![],
+            redo: vec![],
+            transaction: None,
         };
     }
 
-    pub fn len_lines(&mut self) -> usize {
-        self.data.len_lines()
+    pub fn from_file(path: &Path) -> io::Result<Buffer> {
+        let mut buffer = Buffer::new(fs::read_to_string(path)?, language_for_path(path));
+        buffer.path = Some(path.to_path_buf());
+        Ok(buffer)
     }
 
     pub fn select_char_down(&mut self) {
@@ -34,7 +116,10 @@ impl Buffer {
             self.scroll_col = Some(new_col);
             new_col
         };
-        let next_line_idx = selection_line_idx + 1;
+        let next_line_idx = match self.visible_line_after(selection_line_idx) {
+            Some(line) => line,
+            None => return,
+        };
         let next_line_char_idx = self.data.line_to_char(next_line_idx);
         let next_line_len = self.data.line(next_line_idx).len_chars();
         let new_start_pos = if selection_col < next_line_len {
@@ -56,7 +141,10 @@ impl Buffer {
             self.scroll_col = Some(new_col);
             new_col
         };
-        let prev_line_idx = selection_line_idx - 1;
+        let prev_line_idx = match self.visible_line_before(selection_line_idx) {
+            Some(line) => line,
+            None => return,
+        };
         let prev_line_char_idx = self.data.line_to_char(prev_line_idx);
         let prev_line_len = self.data.line(prev_line_idx).len_chars();
         let new_start_pos = if selection_col < prev_line_len {
@@ -81,6 +169,168 @@ impl Buffer {
         self.scroll_col = Some(self.get_root_selection_col());
     }
 
+    /// Moves every selection to the position `motion` returns for it, leaving the ones it
+    /// returns `None` for alone. Forward motions start from the last character of a selection,
+    /// backward ones from its first. With `extend`, the selections grow to the new position
+    /// instead: forward motions move their end and backward ones their start.
+    fn apply_motion<F>(&mut self, extend: bool, forward: bool, motion: F)
+    where
+        F: Fn(&Rope, usize) -> Option<usize>,
+    {
+        let data = self.data.clone();
+        let last = data.len_chars().saturating_sub(1);
+        self.map_selections(|selection| {
+            let from = if forward {
+                selection.end.saturating_sub(1).max(selection.start)
+            } else {
+                selection.start
+            };
+            let to = motion(&data, from)?.min(last);
+            if !extend {
+                Some(to..to + 1)
+            } else if to >= selection.start {
+                Some(selection.start..to + 1)
+            } else {
+                Some(to..selection.end)
+            }
+        });
+    }
+
+    /// Moves to the start of the next word, or of the next WORD with `big`.
+    pub fn select_next_word_start(&mut self, big: bool, extend: bool) {
+        self.apply_motion(extend, true, |data, from| {
+            let word = next_word(data, from, big, |word| word.start > from);
+            Some(word.map_or(data.len_chars(), |word| word.start))
+        });
+    }
+
+    /// Moves to the end of the current word, or of the next one if already at its end.
+    pub fn select_next_word_end(&mut self, big: bool, extend: bool) {
+        self.apply_motion(extend, true, |data, from| {
+            let word = next_word(data, from, big, |word| word.end - 1 > from);
+            Some(word.map_or(data.len_chars(), |word| word.end - 1))
+        });
+    }
+
+    /// Moves to the start of the current word, or of the previous one if already at its start.
+    pub fn select_prev_word_start(&mut self, big: bool, extend: bool) {
+        self.apply_motion(extend, false, |data, from| {
+            let word = prev_word(data, from, big, |word| word.start < from);
+            Some(word.map_or(0, |word| word.start))
+        });
+    }
+
+    /// Moves to the first character of the line.
+    pub fn select_line_start(&mut self, extend: bool) {
+        self.apply_motion(extend, false, |data, from| {
+            Some(data.line_to_char(data.char_to_line(from)))
+        });
+    }
+
+    /// Moves to the first character of the line that is not whitespace.
+    pub fn select_line_first_non_blank(&mut self, extend: bool) {
+        self.apply_motion(extend, false, |data, from| {
+            let start = data.line_to_char(data.char_to_line(from));
+            let indent = data
+                .line(data.char_to_line(from))
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count();
+            Some(start + indent)
+        });
+    }
+
+    /// Moves to the last character of the line before its line break.
+    pub fn select_line_end(&mut self, extend: bool) {
+        self.apply_motion(extend, true, |data, from| {
+            let line = data.char_to_line(from);
+            let start = data.line_to_char(line);
+            let len = line_len(data, line);
+            Some(start + len.saturating_sub(1))
+        });
+    }
+
+    /// Moves to the blank line after the current paragraph, or to the end of the buffer.
+    pub fn select_next_paragraph(&mut self, extend: bool) {
+        self.apply_motion(extend, true, |data, from| {
+            let line = data.char_to_line(from);
+            let blank = (line + 1..data.len_lines())
+                .find(|line| is_blank(data, *line) && !is_blank(data, line - 1));
+            Some(blank.map_or(data.len_chars(), |line| data.line_to_char(line)))
+        });
+    }
+
+    /// Moves to the blank line before the current paragraph, or to the start of the buffer.
+    pub fn select_prev_paragraph(&mut self, extend: bool) {
+        self.apply_motion(extend, false, |data, from| {
+            let line = data.char_to_line(from);
+            let blank = (1..line)
+                .rev()
+                .find(|line| is_blank(data, *line) && !is_blank(data, line + 1));
+            Some(blank.map_or(0, |line| data.line_to_char(line)))
+        });
+    }
+
+    /// Moves to the first character of the buffer.
+    pub fn select_document_start(&mut self, extend: bool) {
+        self.apply_motion(extend, false, |_, _| Some(0));
+    }
+
+    /// Moves to the start of `line`, or of the last line if there are fewer lines.
+    pub fn select_line(&mut self, line: usize, extend: bool) {
+        self.apply_motion(extend, true, |data, _| {
+            Some(data.line_to_char(line.min(data.len_lines() - 1)))
+        });
+    }
+
+    /// Moves to the start of the last line of the buffer that is not empty.
+    pub fn select_document_end(&mut self, extend: bool) {
+        self.apply_motion(extend, true, |data, _| {
+            let last = (0..data.len_lines())
+                .rev()
+                .find(|line| line_len(data, *line) > 0)
+                .unwrap_or(0);
+            Some(data.line_to_char(last))
+        });
+    }
+
+    /// Moves to the next or previous occurrence of a character, or next to it for a till
+    /// search. Repeating a till search skips the occurrence right next to the selection, which
+    /// it would otherwise stay stuck on. Searches stop at the end of the line unless
+    /// `across_lines` is set.
+    pub fn select_char_search(
+        &mut self,
+        search: CharSearch,
+        repeat: bool,
+        across_lines: bool,
+        extend: bool,
+    ) {
+        self.apply_motion(extend, search.forward, |data, from| {
+            let line = data.char_to_line(from);
+            let skip = if search.till && repeat { 2 } else { 1 };
+            if search.forward {
+                let end = if across_lines {
+                    data.len_chars()
+                } else {
+                    data.line_to_char(line) + line_len(data, line)
+                };
+                let found =
+                    (from + skip..end).find(|position| data.char(*position) == search.target)?;
+                Some(if search.till { found - 1 } else { found })
+            } else {
+                let start = if across_lines {
+                    0
+                } else {
+                    data.line_to_char(line)
+                };
+                let found = (start..from.saturating_sub(skip - 1))
+                    .rev()
+                    .find(|position| data.char(*position) == search.target)?;
+                Some(if search.till { found + 1 } else { found })
+            }
+        });
+    }
+
     pub fn get_root_selection(&self) -> Selection {
         self.selections[0].clone()
     }
@@ -95,4 +345,1556 @@ impl Buffer {
         let line_pos = self.data.line_to_char(line_idx);
         selection.start - line_pos
     }
+
+    /// Returns the character ranges of the syntax errors in the buffer. Missing nodes are zero
+    /// width in the tree, so they are widened to the character they were expected at.
+    pub fn syntax_errors(&self) -> Vec<Selection> {
+        let len_chars = self.data.len_chars();
+        let errors = match &self.syntax {
+            Some(syntax) => syntax.errors(),
+            None => return vec![],
+        };
+        errors
+            .iter()
+            .map(|range| {
+                let start = self.data.byte_to_char(range.start);
+                let end = self.data.byte_to_char(range.end);
+                if start == end {
+                    start.min(len_chars.saturating_sub(1))..(end + 1).min(len_chars)
+                } else {
+                    start..end
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the character ranges of every occurrence of the symbol under the primary
+    /// selection.
+    pub fn references_under_cursor(&self) -> Vec<Selection> {
+        let syntax = match &self.syntax {
+            Some(syntax) => syntax,
+            None => return vec![],
+        };
+        let byte = self.data.char_to_byte(self.get_root_selection().start);
+        syntax
+            .references(&self.data, byte)
+            .iter()
+            .map(|range| self.data.byte_to_char(range.start)..self.data.byte_to_char(range.end))
+            .collect()
+    }
+
+    pub fn select_next_error(&mut self) {
+        let position = self.get_root_selection().start;
+        if let Some(error) = self
+            .syntax_errors()
+            .into_iter()
+            .find(|error| error.start > position)
+        {
+            self.select_error(error);
+        }
+    }
+
+    pub fn select_prev_error(&mut self) {
+        let position = self.get_root_selection().start;
+        if let Some(error) = self
+            .syntax_errors()
+            .into_iter()
+            .rev()
+            .find(|error| error.start < position)
+        {
+            self.select_error(error);
+        }
+    }
+
+    fn select_error(&mut self, error: Selection) {
+        self.selections[0] = error.start..error.start + 1;
+        self.scroll_col = Some(self.get_root_selection_col());
+    }
+
+    /// Replaces every selection with the selection `f` returns for it, leaving the selections it
+    /// returns `None` for unchanged. Returns whether any selection changed.
+    fn map_selections<F>(&mut self, f: F) -> bool
+    where
+        F: Fn(Selection) -> Option<Selection>,
+    {
+        let selections: Selections = self
+            .selections
+            .iter()
+            .map(|selection| f(selection.clone()).unwrap_or_else(|| selection.clone()))
+            .collect();
+        if selections == self.selections {
+            return false;
+        }
+        self.selections = selections;
+        self.dedup_selections();
+        self.scroll_col = Some(self.get_root_selection_col());
+        true
+    }
+
+    /// Like `map_selections`, but `f` is given the syntax tree and works with byte ranges.
+    fn map_selections_in_tree<F>(&mut self, f: F) -> bool
+    where
+        F: Fn(&Syntax, Range<usize>) -> Option<Range<usize>>,
+    {
+        let syntax = match self.syntax.take() {
+            Some(syntax) => syntax,
+            None => return false,
+        };
+        let data = self.data.clone();
+        let changed = self.map_selections(|selection| {
+            let range = data.char_to_byte(selection.start)..data.char_to_byte(selection.end);
+            f(&syntax, range)
+                .map(|range| data.byte_to_char(range.start)..data.byte_to_char(range.end))
+        });
+        self.syntax = Some(syntax);
+        changed
+    }
+
+    /// Removes selections that are identical to an earlier one.
+    fn dedup_selections(&mut self) {
+        let mut seen = vec![];
+        self.selections.retain(|selection| {
+            if seen.contains(selection) {
+                false
+            } else {
+                seen.push(selection.clone());
+                true
+            }
+        });
+    }
+
+    /// Replaces the selections with `selections`, the first being the primary one, unless there
+    /// are none. Returns whether they were replaced.
+    fn set_selections(&mut self, selections: Selections) -> bool {
+        if selections.is_empty() {
+            return false;
+        }
+        self.selections = selections;
+        self.dedup_selections();
+        self.scroll_col = Some(self.get_root_selection_col());
+        true
+    }
+
+    /// Returns the matches of `regex` that lie inside `selection`.
+    fn matches_in(&self, regex: &RopeRegex, selection: &Selection) -> Selections {
+        regex
+            .find_all(&self.data, selection.clone())
+            .into_iter()
+            .filter(|m| m.end <= selection.end)
+            .collect()
+    }
+
+    /// Selects every match of `regex` inside the selections. Returns false, leaving the
+    /// selections alone, when there is none.
+    pub fn select_matches(&mut self, regex: &RopeRegex) -> bool {
+        let selections = self
+            .selections
+            .iter()
+            .flat_map(|selection| self.matches_in(regex, selection))
+            .collect();
+        self.set_selections(selections)
+    }
+
+    /// Splits the selections on the matches of `regex`, dropping the matches and the empty
+    /// pieces between them. Returns false, leaving the selections alone, when nothing is left.
+    pub fn split_selections(&mut self, regex: &RopeRegex) -> bool {
+        let mut selections = vec![];
+        for selection in &self.selections {
+            let mut start = selection.start;
+            for m in self.matches_in(regex, selection) {
+                if m.start > start {
+                    selections.push(start..m.start);
+                }
+                start = m.end;
+            }
+            if selection.end > start {
+                selections.push(start..selection.end);
+            }
+        }
+        self.set_selections(selections)
+    }
+
+    /// Keeps the selections that contain a match of `regex`, or the ones that do not when `keep`
+    /// is false. Returns false, leaving the selections alone, when none would be left.
+    pub fn keep_matching_selections(&mut self, regex: &RopeRegex, keep: bool) -> bool {
+        let selections = self
+            .selections
+            .iter()
+            .filter(|selection| self.matches_in(regex, selection).is_empty() != keep)
+            .cloned()
+            .collect();
+        self.set_selections(selections)
+    }
+
+    /// Splits every selection into one selection per line, each with its line ending.
+    pub fn split_selections_into_lines(&mut self) {
+        let mut selections = vec![];
+        for selection in &self.selections {
+            let mut start = selection.start;
+            while start < selection.end {
+                let line = self.data.char_to_line(start);
+                let end = self.data.line_to_char(line + 1).min(selection.end);
+                selections.push(start..end);
+                start = end;
+            }
+        }
+        self.set_selections(selections);
+    }
+
+    /// Merges the selections that overlap or touch into one. The primary selection becomes the one
+    /// it was merged into.
+    pub fn merge_selections(&mut self) {
+        let primary = self.selections[0].start;
+        let mut sorted = self.selections.clone();
+        sorted.sort_by_key(|selection| selection.start);
+        let mut merged: Selections = vec![];
+        for selection in sorted {
+            match merged.last_mut() {
+                Some(last) if selection.start <= last.end => last.end = last.end.max(selection.end),
+                _ => merged.push(selection),
+            }
+        }
+        let index = merged
+            .iter()
+            .position(|selection| selection.contains(&primary))
+            .unwrap_or(0);
+        let selection = merged.remove(index);
+        merged.insert(0, selection);
+        self.set_selections(merged);
+    }
+
+    /// Drops every selection but the primary one.
+    pub fn keep_primary_selection(&mut self) {
+        self.selections.truncate(1);
+    }
+
+    /// Expands every selection to the smallest syntax node that encloses it.
+    pub fn expand_selections(&mut self) {
+        let before = self.selections.clone();
+        if self.map_selections_in_tree(|syntax, range| syntax.expand(range)) {
+            self.expansions.push((before, self.selections.clone()));
+        }
+    }
+
+    /// Undoes the last expansion, or selects the first child node of every selection if the
+    /// selections have changed since.
+    pub fn shrink_selections(&mut self) {
+        if let Some((before, after)) = self.expansions.pop() {
+            if after == self.selections {
+                self.selections = before;
+                self.scroll_col = Some(self.get_root_selection_col());
+                return;
+            }
+            self.expansions.clear();
+        }
+        self.map_selections_in_tree(|syntax, range| syntax.shrink(range));
+    }
+
+    /// Moves every selection to the next or previous sibling of the syntax node it covers.
+    pub fn select_sibling(&mut self, forward: bool) {
+        self.map_selections_in_tree(|syntax, range| syntax.sibling(range, forward));
+    }
+
+    /// Extends every selection over the next or previous sibling of the syntax node it covers.
+    pub fn extend_to_sibling(&mut self, forward: bool) {
+        self.map_selections_in_tree(|syntax, range| {
+            let sibling = syntax.sibling(range.clone(), forward)?;
+            Some(range.start.min(sibling.start)..range.end.max(sibling.end))
+        });
+    }
+
+    /// Returns the position of the bracket under the primary selection and of the bracket that
+    /// matches it.
+    pub fn matching_brackets(&self) -> Option<(usize, usize)> {
+        let syntax = self.syntax.as_ref()?;
+        let position = self.get_root_selection().start;
+        let other = syntax.matching_bracket(self.data.char_to_byte(position))?;
+        Some((position, self.data.byte_to_char(other)))
+    }
+
+    /// Moves every selection to the bracket that matches the first bracket at or after its start
+    /// on the same line.
+    pub fn select_matching_bracket(&mut self) {
+        let data = self.data.clone();
+        self.map_selections_in_tree(|syntax, range| {
+            let line_end = data.line_to_byte(data.byte_to_line(range.start) + 1);
+            (range.start..line_end)
+                .find_map(|byte| syntax.matching_bracket(byte))
+                .map(|byte| byte..byte + 1)
+        });
+    }
+
+    /// Returns the positions of the opening and closing brackets of every pair that is at least
+    /// partly inside `range`, with how deeply the pair is nested.
+    pub fn brackets(&self, range: Selection) -> Vec<(usize, usize, usize)> {
+        let syntax = match &self.syntax {
+            Some(syntax) => syntax,
+            None => return vec![],
+        };
+        let range = self.data.char_to_byte(range.start)..self.data.char_to_byte(range.end);
+        syntax
+            .brackets(range)
+            .into_iter()
+            .map(|(open, close, depth)| {
+                (
+                    self.data.byte_to_char(open),
+                    self.data.byte_to_char(close),
+                    depth,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the character ranges of the text objects called `name`, in document order.
+    fn textobjects(&self, name: &str) -> Vec<Selection> {
+        let syntax = match &self.syntax {
+            Some(syntax) => syntax,
+            None => return vec![],
+        };
+        syntax
+            .textobjects(&self.data, name)
+            .iter()
+            .map(|range| self.data.byte_to_char(range.start)..self.data.byte_to_char(range.end))
+            .collect()
+    }
+
+    /// Selects the smallest text object called `name` around every selection. A selection that
+    /// already is such an object grows to the next one around it.
+    pub fn select_textobject(&mut self, name: &str) {
+        let objects = self.textobjects(name);
+        self.map_selections(|selection| {
+            let mut enclosing: Vec<&Selection> = objects
+                .iter()
+                .filter(|object| object.start <= selection.start && selection.end <= object.end)
+                .collect();
+            enclosing.sort_by_key(|object| object.end - object.start);
+            enclosing
+                .iter()
+                .find(|object| ***object != selection)
+                .or_else(|| enclosing.first())
+                .map(|object| (*object).clone())
+        });
+    }
+
+    /// Moves every selection to the next text object called `name` that starts after it.
+    pub fn select_next_textobject(&mut self, name: &str) {
+        let objects = self.textobjects(name);
+        self.map_selections(|selection| {
+            objects
+                .iter()
+                .find(|object| object.start > selection.start)
+                .cloned()
+        });
+    }
+
+    /// Moves every selection to the previous text object called `name` that starts before it.
+    pub fn select_prev_textobject(&mut self, name: &str) {
+        let objects = self.textobjects(name);
+        self.map_selections(|selection| {
+            objects
+                .iter()
+                .rev()
+                .find(|object| object.start < selection.start)
+                .cloned()
+        });
+    }
+
+    /// Returns the outermost closed fold that hides `line`. The first line of a fold stays
+    /// visible, so it is not hidden by it.
+    fn closed_fold_hiding(&self, line: usize) -> Option<&Fold> {
+        self.folds
+            .iter()
+            .find(|fold| fold.closed && fold.start < line && line <= fold.end)
+    }
+
+    /// Returns `line`, or the first line of the closed fold that hides it.
+    pub fn visible_line(&self, line: usize) -> usize {
+        match self.closed_fold_hiding(line) {
+            Some(fold) => fold.start,
+            None => line,
+        }
+    }
+
+    /// Returns the first line after `line` that is not hidden by a closed fold.
+    pub fn visible_line_after(&self, line: usize) -> Option<usize> {
+        let mut next = line + 1;
+        while let Some(fold) = self.closed_fold_hiding(next) {
+            next = fold.end + 1;
+        }
+        if next < self.data.len_lines() {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the last line before `line` that is not hidden by a closed fold.
+    pub fn visible_line_before(&self, line: usize) -> Option<usize> {
+        if line == 0 {
+            return None;
+        }
+        Some(self.visible_line(line - 1))
+    }
+
+    /// Returns the index of the fold under `line`: the outermost closed fold that contains it, or
+    /// else the innermost fold that contains it.
+    fn fold_at(&self, line: usize) -> Option<usize> {
+        let containing = |fold: &&Fold| fold.start <= line && line <= fold.end;
+        if let Some(index) = self
+            .folds
+            .iter()
+            .position(|fold| fold.closed && containing(&fold))
+        {
+            return Some(index);
+        }
+        self.folds.iter().rposition(|fold| containing(&fold))
+    }
+
+    pub fn open_fold(&mut self) {
+        if let Some(index) = self.fold_at(self.get_root_selection_line()) {
+            self.folds[index].closed = false;
+        }
+    }
+
+    /// Closes the innermost open fold under the primary selection and moves the selection to the
+    /// first line of the fold.
+    pub fn close_fold(&mut self) {
+        let line = self.get_root_selection_line();
+        if let Some(index) = self
+            .folds
+            .iter()
+            .rposition(|fold| !fold.closed && fold.start <= line && line <= fold.end)
+        {
+            self.folds[index].closed = true;
+            self.select_fold_start(index);
+        }
+    }
+
+    pub fn toggle_fold(&mut self) {
+        match self.fold_at(self.get_root_selection_line()) {
+            Some(index) if self.folds[index].closed => self.folds[index].closed = false,
+            Some(_) => self.close_fold(),
+            None => {}
+        }
+    }
+
+    pub fn open_all_folds(&mut self) {
+        self.folds.iter_mut().for_each(|fold| fold.closed = false);
+    }
+
+    pub fn close_all_folds(&mut self) {
+        self.folds.iter_mut().for_each(|fold| fold.closed = true);
+        let line = self.visible_line(self.get_root_selection_line());
+        if line != self.get_root_selection_line() {
+            let start = self.data.line_to_char(line);
+            self.selections[0] = start..start + 1;
+            self.scroll_col = None;
+        }
+    }
+
+    fn select_fold_start(&mut self, index: usize) {
+        let line = self.folds[index].start;
+        if self.get_root_selection_line() != line {
+            let start = self.data.line_to_char(line);
+            self.selections[0] = start..start + 1;
+            self.scroll_col = None;
+        }
+    }
+
+    /// Opens the closed folds that hide the primary selection.
+    pub fn reveal_selection(&mut self) {
+        let line = self.get_root_selection_line();
+        for fold in self.folds.iter_mut() {
+            if fold.closed && fold.start < line && line <= fold.end {
+                fold.closed = false;
+            }
+        }
+    }
+
+    /// Replaces all selections with a single one at `position`.
+    pub fn select_at(&mut self, position: usize) {
+        self.selections.truncate(1);
+        self.selections[0] = position..position + 1;
+        self.scroll_col = Some(self.get_root_selection_col());
+    }
+
+    /// Makes `range` the primary selection, or extends the primary selection over it.
+    pub fn select_range(&mut self, range: Range<usize>, extend: bool) {
+        let primary = &mut self.selections[0];
+        *primary = if extend {
+            primary.start.min(range.start)..primary.end.max(range.end)
+        } else {
+            range
+        };
+        self.dedup_selections();
+        self.scroll_col = Some(self.get_root_selection_col());
+    }
+
+    /// Moves the primary selection to the first line of the innermost fold that encloses it,
+    /// which is the signature of the function or type it is in.
+    pub fn select_context_start(&mut self) {
+        let line = self.get_root_selection_line();
+        if let Some(fold) = self
+            .folds
+            .iter()
+            .rev()
+            .find(|fold| fold.start < line && line <= fold.end)
+        {
+            let line_start = self.data.line_to_char(fold.start);
+            let indent = self
+                .data
+                .line(fold.start)
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .count();
+            self.selections[0] = line_start + indent..line_start + indent + 1;
+            self.scroll_col = Some(indent);
+        }
+    }
+
+    fn revision(&self) -> Revision {
+        Revision {
+            data: self.data.clone(),
+            selections: self.selections.clone(),
+        }
+    }
+
+    /// Groups the changes made until `end_transaction` into a single one for undo.
+    pub fn begin_transaction(&mut self) {
+        if self.transaction.is_none() {
+            self.transaction = Some(self.revision());
+        }
+    }
+
+    pub fn end_transaction(&mut self) {
+        if let Some(revision) = self.transaction.take() {
+            if revision.data != self.data {
+                self.undo.push(revision);
+                self.redo.clear();
+            }
+        }
+    }
+
+    /// Reverts the last change. Returns whether there was one.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(revision) => {
+                let current = self.revision();
+                self.redo.push(current);
+                self.restore(revision);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the last undone change again. Returns whether there was one.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(revision) => {
+                let current = self.revision();
+                self.undo.push(current);
+                self.restore(revision);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn restore(&mut self, revision: Revision) {
+        self.data = revision.data;
+        // The tree of another revision has nothing to reuse.
+        self.syntax = None;
+        self.selections = revision.selections;
+        self.scroll_col = Some(self.get_root_selection_col());
+        self.reparse();
+    }
+
+    /// Replaces every range with its text as a single change. The ranges must not overlap.
+    pub fn replace(&mut self, changes: &[(Selection, String)]) {
+        self.apply_changes(changes);
+    }
+
+    /// Replaces every range with its text, moving the selections along with the text around
+    /// them. The ranges must not overlap. Returns where the text of each change starts
+    /// afterwards.
+    fn apply_changes(&mut self, changes: &[(Selection, String)]) -> Vec<usize> {
+        if self.transaction.is_none() {
+            let revision = self.revision();
+            self.undo.push(revision);
+            self.redo.clear();
+        }
+        let delta = |(range, text): &(Selection, String)| {
+            text.chars().count() as isize - range.len() as isize
+        };
+        let map = |position: usize| {
+            let mut mapped = position as isize;
+            for change in changes {
+                let range = &change.0;
+                if position >= range.end {
+                    mapped += delta(change);
+                } else if position > range.start {
+                    mapped -= (position - range.start) as isize;
+                }
+            }
+            mapped as usize
+        };
+        let starts = changes
+            .iter()
+            .map(|(range, _)| {
+                let before: isize = changes
+                    .iter()
+                    .filter(|(other, _)| other.start < range.start && other.end <= range.start)
+                    .map(delta)
+                    .sum();
+                (range.start as isize + before) as usize
+            })
+            .collect();
+        let selections: Selections = self
+            .selections
+            .iter()
+            .map(|selection| map(selection.start)..map(selection.end))
+            .collect();
+
+        let mut order: Vec<&(Selection, String)> = changes.iter().collect();
+        order.sort_by_key(|(range, _)| Reverse(range.start));
+        for (range, text) in order {
+            self.splice(range.clone(), text);
+        }
+
+        let len_chars = self.data.len_chars();
+        self.selections = selections
+            .into_iter()
+            .map(|selection| {
+                let start = selection.start.min(len_chars.saturating_sub(1));
+                start..selection.end.min(len_chars).max(start + 1)
+            })
+            .collect();
+        self.dedup_selections();
+        self.scroll_col = Some(self.get_root_selection_col());
+        self.reparse();
+        starts
+    }
+
+    /// Replaces `range` with `text`, editing the syntax tree along with it.
+    fn splice(&mut self, range: Selection, text: &str) {
+        let start_byte = self.data.char_to_byte(range.start);
+        let old_end_byte = self.data.char_to_byte(range.end);
+        let start_position = byte_to_point(&self.data, start_byte);
+        let old_end_position = byte_to_point(&self.data, old_end_byte);
+        self.data.remove(range.clone());
+        self.data.insert(range.start, text);
+        if let Some(syntax) = &mut self.syntax {
+            let new_end_byte = start_byte + text.len();
+            syntax.edit(&InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte,
+                start_position,
+                old_end_position,
+                new_end_position: byte_to_point(&self.data, new_end_byte),
+            });
+        }
+    }
+
+    /// Brings the syntax tree and the folds up to date after an edit. Folds that start on the
+    /// same line as a closed fold did stay closed.
+    fn reparse(&mut self) {
+        match &mut self.syntax {
+            Some(syntax) => syntax.update(&self.data),
+            None => {
+                self.syntax = self
+                    .language
+                    .map(|language| Syntax::new(language, &self.data))
+            }
+        }
+        let closed: Vec<usize> = self
+            .folds
+            .iter()
+            .filter(|fold| fold.closed)
+            .map(|fold| fold.start)
+            .collect();
+        self.folds = compute_folds(&self.data, self.syntax.as_ref());
+        for fold in &mut self.folds {
+            fold.closed = closed.contains(&fold.start);
+        }
+        self.expansions.clear();
+        self.version = next_version();
+    }
+
+    /// Returns the char range of `lines`, the lines past the end of the buffer left out.
+    pub fn line_chars(&self, lines: Range<usize>) -> Selection {
+        let end = lines.end.min(self.data.len_lines());
+        self.data.line_to_char(lines.start.min(end))..self.data.line_to_char(end)
+    }
+
+    /// Returns the positions text is inserted at: the start of every selection.
+    fn cursors(&self) -> Vec<usize> {
+        let mut cursors: Vec<usize> = self
+            .selections
+            .iter()
+            .map(|selection| selection.start)
+            .collect();
+        cursors.sort_unstable();
+        cursors.dedup();
+        cursors
+    }
+
+    /// Inserts `text` before every selection.
+    pub fn insert(&mut self, text: &str) {
+        let changes: Vec<(Selection, String)> = self
+            .cursors()
+            .into_iter()
+            .map(|cursor| (cursor..cursor, String::from(text)))
+            .collect();
+        self.apply_changes(&changes);
+    }
+
+    /// Types `c` before every selection. Opening brackets and quotes are inserted together with
+    /// their closing counterpart, and typing a closing one that is already under the cursor
+    /// steps over it.
+    pub fn insert_char(&mut self, c: char) {
+        let cursors = self.cursors();
+        let changes: Vec<(Selection, String)> = cursors
+            .iter()
+            .map(|&cursor| {
+                if self.closes_at(cursor, c) {
+                    (cursor..cursor + 1, c.to_string())
+                } else if let Some(close) = self.pair_at(cursor, c) {
+                    (cursor..cursor, format!("{}{}", c, close))
+                } else {
+                    (cursor..cursor, c.to_string())
+                }
+            })
+            .collect();
+
+        let selections = self.selections.clone();
+        let starts = self.apply_changes(&changes);
+        let positions: Vec<usize> = starts.iter().map(|start| start + 1).collect();
+        self.move_to_cursors(selections, &cursors, &positions);
+    }
+
+    /// Returns whether typing `c` at `position` should step over the same character instead.
+    fn closes_at(&self, position: usize, c: char) -> bool {
+        if position >= self.data.len_chars() || self.data.char(position) != c {
+            return false;
+        }
+        match AUTO_PAIRS.iter().find(|(_, close)| *close == c) {
+            Some((open, _)) if open == &c => self
+                .syntax
+                .as_ref()
+                .is_none_or(|syntax| syntax.closes_literal(self.data.char_to_byte(position))),
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Returns the character that closes `c` if typing `c` at `position` should insert a pair.
+    ///
+    /// Pairs are not inserted in front of a word, or inside strings and comments. Quotes are not
+    /// paired after a word either, and `'` not where it starts a lifetime, such as `'a` in Rust.
+    fn pair_at(&self, position: usize, c: char) -> Option<char> {
+        let &(open, close) = AUTO_PAIRS.iter().find(|(open, _)| *open == c)?;
+        let next = (position < self.data.len_chars()).then(|| self.data.char(position));
+        let before_word = next.is_some_and(|next| {
+            !next.is_whitespace() && !AUTO_PAIRS.iter().any(|(_, close)| *close == next)
+        });
+        if before_word {
+            return None;
+        }
+        if let Some(syntax) = &self.syntax {
+            if syntax.in_string_or_comment(self.data.char_to_byte(position)) {
+                return None;
+            }
+        }
+        if open == close {
+            let after_word = position > 0 && self.data.char(position - 1).is_alphanumeric();
+            let lifetime = open == '\''
+                && self.language.is_some_and(|language| language.lifetimes)
+                && self.starts_lifetime(position);
+            if after_word || lifetime {
+                return None;
+            }
+        }
+        Some(close)
+    }
+
+    /// Returns the last token that ends before `position`, whitespace left out.
+    fn token_before(&self, position: usize) -> Option<Node<'_>> {
+        let syntax = self.syntax.as_ref()?;
+        let end = (0..position)
+            .rev()
+            .find(|position| !self.data.char(*position).is_whitespace())?
+            + 1;
+        let end = self.data.char_to_byte(end);
+        let start = self.data.char_to_byte(self.data.byte_to_char(end - 1));
+        syntax
+            .tree()
+            .root_node()
+            .descendant_for_byte_range(start, end)
+    }
+
+    /// Returns whether a `'` typed at `position` starts a lifetime or a label rather than a
+    /// character literal, judging by the tokens before it: `&'a`, `T: 'a + Send`, `Foo<'a, 'b>`
+    /// and `break 'outer` start lifetimes and labels, while `c < 'a'` and `f('a', 'b')` do not.
+    fn starts_lifetime(&self, position: usize) -> bool {
+        let token = match self.token_before(position) {
+            Some(token) => token,
+            None => return false,
+        };
+        let previous = self.token_before(self.data.byte_to_char(token.start_byte()));
+        // Incomplete code often parses type parameters as plain identifiers, which are told
+        // apart by being capitalized.
+        let is_type = previous.is_some_and(|previous| {
+            let start = self.data.byte_to_char(previous.start_byte());
+            previous.kind() == "type_identifier"
+                || (previous.kind() == "identifier" && self.data.char(start).is_uppercase())
+        });
+        let in_lifetime = previous.is_some_and(|previous| {
+            previous.kind() == "lifetime"
+                || previous
+                    .parent()
+                    .is_some_and(|parent| parent.kind() == "lifetime")
+        });
+        match token.kind() {
+            "&" | "+" | "break" | "continue" => true,
+            // Type arguments follow their type without a space, comparisons are spaced out.
+            "<" => {
+                token.end_byte() == self.data.char_to_byte(position)
+                    && previous.is_some_and(|previous| previous.end_byte() == token.start_byte())
+            }
+            "," => in_lifetime,
+            ":" => in_lifetime || is_type,
+            _ => false,
+        }
+    }
+
+    /// Inserts one level of indentation before every selection.
+    pub fn insert_indent(&mut self) {
+        self.insert(&self.indent.unit());
+    }
+
+    /// Deletes the character before every selection, together with the character under the
+    /// selection if the two are an empty pair such as `()`.
+    pub fn delete_char_backward(&mut self) {
+        let len_chars = self.data.len_chars();
+        let mut changes: Vec<(Selection, String)> = vec![];
+        for cursor in self.cursors().into_iter().filter(|cursor| *cursor > 0) {
+            let pair = cursor < len_chars
+                && AUTO_PAIRS.contains(&(self.data.char(cursor - 1), self.data.char(cursor)));
+            let range = if pair {
+                cursor - 1..cursor + 1
+            } else {
+                cursor - 1..cursor
+            };
+            // Deleting a pair also deletes the character before the next cursor, so the ranges of
+            // neighbouring cursors are merged to keep them from overlapping.
+            match changes.last_mut() {
+                Some((last, _)) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => changes.push((range, String::new())),
+            }
+        }
+        self.apply_changes(&changes);
+    }
+
+    /// Breaks the line before every selection and indents the new line. Whitespace around the
+    /// break is dropped. Breaking between a pair of brackets puts the closing bracket on a line
+    /// of its own, so that the cursor lands on an indented empty line between them.
+    pub fn insert_newline(&mut self) {
+        let mut changes = vec![];
+        let mut offsets = vec![];
+        let cursors = self.cursors();
+        for &cursor in &cursors {
+            let line_start = self.data.line_to_char(self.data.char_to_line(cursor));
+            let is_blank = |c: char| c == ' ' || c == '\t';
+            let before = (line_start..cursor)
+                .rev()
+                .take_while(|position| is_blank(self.data.char(*position)))
+                .count();
+            let after = (cursor..self.data.len_chars())
+                .take_while(|position| is_blank(self.data.char(*position)))
+                .count();
+            let start = cursor - before;
+            let end = cursor + after;
+
+            let indentation = self.indentation(end);
+            let between_pair = start > line_start
+                && end < self.data.len_chars()
+                && self.syntax.as_ref().is_some_and(|syntax| {
+                    syntax.matching_bracket(self.data.char_to_byte(start - 1))
+                        == Some(self.data.char_to_byte(end))
+                });
+            if between_pair {
+                let inner = indentation.clone() + &self.indent.unit();
+                offsets.push(1 + inner.chars().count());
+                changes.push((start..end, format!("\n{}\n{}", inner, indentation)));
+            } else {
+                offsets.push(1 + indentation.chars().count());
+                changes.push((start..end, format!("\n{}", indentation)));
+            }
+        }
+
+        let selections = self.selections.clone();
+        let starts = self.apply_changes(&changes);
+        let positions: Vec<usize> = starts
+            .iter()
+            .zip(&offsets)
+            .map(|(start, offset)| start + offset)
+            .collect();
+        self.move_to_cursors(selections, &cursors, &positions);
+    }
+
+    /// Moves each of `selections` to where the cursor at its start went, keeping its width and
+    /// the order of the selections. `cursors` are the positions that changes were made at, in
+    /// order, and `positions` where the cursor of each change is afterwards.
+    fn move_to_cursors(&mut self, selections: Selections, cursors: &[usize], positions: &[usize]) {
+        let len_chars = self.data.len_chars();
+        self.selections = selections
+            .into_iter()
+            .map(|selection| {
+                let index = cursors
+                    .binary_search(&selection.start)
+                    .expect("every selection starts at a cursor");
+                let start = positions[index].min(len_chars.saturating_sub(1));
+                start..(start + selection.len()).min(len_chars).max(start + 1)
+            })
+            .collect();
+        self.dedup_selections();
+        self.scroll_col = Some(self.get_root_selection_col());
+    }
+
+    /// Returns the indentation of a line that starts at `position`, from the language's indents
+    /// query, or else copied from the closest line before that is not blank.
+    fn indentation(&self, position: usize) -> String {
+        let byte = self.data.char_to_byte(position);
+        let level = self
+            .syntax
+            .as_ref()
+            .and_then(|syntax| syntax.indent_level(&self.data, byte));
+        if let Some(level) = level {
+            return self.indent.unit().repeat(level);
+        }
+
+        let line = self.data.char_to_line(position);
+        let lines_before = if position > self.data.line_to_char(line) {
+            line + 1
+        } else {
+            line
+        };
+        (0..lines_before)
+            .rev()
+            .map(|line| self.data.line(line))
+            .find(|text| text.chars().any(|c| !c.is_whitespace()))
+            .map(|text| {
+                text.chars()
+                    .take_while(|c| *c == ' ' || *c == '\t')
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the lines that `selection` touches, and at least `count` lines from its first.
+    fn lines_of(&self, selection: &Selection, count: usize) -> RangeInclusive<usize> {
+        let last = selection.end.saturating_sub(1).max(selection.start);
+        let first = self.data.char_to_line(selection.start);
+        let last = self
+            .data
+            .char_to_line(last)
+            .max(first + count - 1)
+            .min(self.data.len_lines() - 1);
+        first..=last
+    }
+
+    /// Reindents every line that a selection touches, and at least `count` lines from its first.
+    /// Blank lines are left alone.
+    pub fn reindent_selections(&mut self, count: usize) {
+        let mut lines: Vec<usize> = self
+            .selections
+            .iter()
+            .flat_map(|selection| self.lines_of(selection, count))
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+
+        let changes: Vec<(Selection, String)> = lines
+            .into_iter()
+            .filter_map(|line| {
+                let text = self.data.line(line);
+                if text.chars().all(char::is_whitespace) {
+                    return None;
+                }
+                let start = self.data.line_to_char(line);
+                let current = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+                let indentation = self.indentation(start);
+                if text.slice(..current) == indentation.as_str() {
+                    None
+                } else {
+                    Some((start..start + current, indentation))
+                }
+            })
+            .collect();
+        if !changes.is_empty() {
+            self.apply_changes(&changes);
+        }
+    }
+
+    /// Returns the language at `position`: the language injected there, such as the language of
+    /// a Markdown code block, or else the buffer's.
+    fn language_at(&self, position: usize) -> Option<&'static LanguageConfig> {
+        let byte = self.data.char_to_byte(position);
+        self.syntax
+            .as_ref()
+            .and_then(|syntax| syntax.injected_language(&self.data, byte))
+            .and_then(|name| language_by_name(&name))
+            .or(self.language)
+    }
+
+    /// Comments out the lines every selection touches, and at least `count` lines from its first,
+    /// or uncomments them if they are all commented out already. Line comments are inserted at
+    /// the smallest indentation of the lines; languages without them wrap the lines in a block
+    /// comment instead. The comment tokens are those of the language at the start of the
+    /// selection, so that code blocks in Markdown get the comments of their own language.
+    pub fn toggle_comments(&mut self, count: usize) {
+        let mut changes = vec![];
+        let mut done: Vec<usize> = vec![];
+        for selection in &self.selections {
+            let lines: Vec<usize> = self
+                .lines_of(selection, count)
+                .filter(|line| !done.contains(line))
+                .filter(|line| !self.data.line(*line).chars().all(char::is_whitespace))
+                .collect();
+            if lines.is_empty() {
+                continue;
+            }
+            done.extend(&lines);
+
+            // The lines as the positions their text starts and ends at, without indentation and
+            // line breaks.
+            let spans: Vec<(usize, usize, usize)> = lines
+                .iter()
+                .map(|line| {
+                    let text = self.data.line(*line);
+                    let indent = text.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+                    let len = text
+                        .to_string()
+                        .trim_end_matches(['\r', '\n'])
+                        .chars()
+                        .count();
+                    let start = self.data.line_to_char(*line);
+                    (start, start + indent, start + len)
+                })
+                .collect();
+            let starts_with = |position: usize, token: &str| {
+                let end = position + token.chars().count();
+                end <= self.data.len_chars() && self.data.slice(position..end) == token
+            };
+
+            let language = self.language_at(spans[0].1);
+            let tokens = language.map(|language| &language.comment_tokens);
+            if let Some(token) = tokens.and_then(|tokens| tokens.line) {
+                let len = token.chars().count();
+                if spans.iter().all(|(_, text, _)| starts_with(*text, token)) {
+                    for (_, text, _) in &spans {
+                        let space = starts_with(text + len, " ") as usize;
+                        changes.push((*text..text + len + space, String::new()));
+                    }
+                } else {
+                    let indent = spans
+                        .iter()
+                        .map(|(start, text, _)| text - start)
+                        .min()
+                        .unwrap();
+                    for (start, _, _) in &spans {
+                        changes.push((start + indent..start + indent, format!("{} ", token)));
+                    }
+                }
+            } else if let Some((open, close)) = tokens.and_then(|tokens| tokens.block) {
+                let start = spans[0].1;
+                let end = spans[spans.len() - 1].2;
+                let (open_len, close_len) = (open.chars().count(), close.chars().count());
+                if end - start >= open_len + close_len
+                    && starts_with(start, open)
+                    && starts_with(end - close_len, close)
+                {
+                    let open_space = starts_with(start + open_len, " ") as usize;
+                    let close_space = (end - close_len > start + open_len + open_space
+                        && starts_with(end - close_len - 1, " "))
+                        as usize;
+                    changes.push((start..start + open_len + open_space, String::new()));
+                    changes.push((end - close_len - close_space..end, String::new()));
+                } else {
+                    changes.push((start..start, format!("{} ", open)));
+                    changes.push((end..end, format!(" {}", close)));
+                }
+            }
+        }
+        if !changes.is_empty() {
+            changes.sort_by_key(|(range, _)| range.start);
+            self.apply_changes(&changes);
+        }
+    }
+
+    /// Returns the definitions in the buffer, in document order.
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let syntax = match &self.syntax {
+            Some(syntax) => syntax,
+            None => return vec![],
+        };
+        let tags = syntax.tags(&self.data);
+        tags.iter()
+            .map(|tag| Symbol {
+                kind: tag.kind.clone(),
+                name: tag.name.clone(),
+                range: self.data.byte_to_char(tag.range.start)
+                    ..self.data.byte_to_char(tag.range.end),
+                name_start: self.data.byte_to_char(tag.name_start),
+                depth: tags
+                    .iter()
+                    .filter(|other| {
+                        other.range != tag.range
+                            && other.range.start <= tag.range.start
+                            && tag.range.end <= other.range.end
+                    })
+                    .count(),
+            })
+            .collect()
+    }
+}
+
+/// Computes the folds of `data` from its syntax tree, or from its indentation if the language
+/// does not define folds.
+fn compute_folds(data: &Rope, syntax: Option<&Syntax>) -> Vec<Fold> {
+    let mut folds = match syntax.and_then(|syntax| syntax.folds(data)) {
+        Some(folds) => folds,
+        None => indent_folds(data),
+    };
+    // Outer folds go before the folds nested in them.
+    folds.sort_by_key(|&(start, end)| (start, Reverse(end)));
+    folds.dedup_by_key(|fold| fold.0);
+    folds
+        .into_iter()
+        .map(|(start, end)| Fold {
+            start,
+            end,
+            closed: false,
+        })
+        .collect()
+}
+
+/// Folds every line together with the lines after it that are indented further. Blank lines
+/// inside such a run belong to it.
+fn indent_folds(data: &Rope) -> Vec<(usize, usize)> {
+    let indents: Vec<Option<usize>> = data
+        .lines()
+        .map(|line| {
+            let indent = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+            let blank = line.chars().skip(indent).all(|c| c == '\n' || c == '\r');
+            if blank {
+                None
+            } else {
+                Some(indent)
+            }
+        })
+        .collect();
+
+    let mut folds = vec![];
+    for (start, indent) in indents.iter().enumerate() {
+        let indent = match indent {
+            Some(indent) => indent,
+            None => continue,
+        };
+        let mut end = start;
+        for (line, other) in indents.iter().enumerate().skip(start + 1) {
+            match other {
+                Some(other) if other > indent => end = line,
+                Some(_) => break,
+                None => {}
+            }
+        }
+        if end > start {
+            folds.push((start, end));
+        }
+    }
+    folds
+}
+
+/// Returns a buffer version that was not handed out before.
+fn next_version() -> usize {
+    static NEXT_VERSION: AtomicUsize = AtomicUsize::new(0);
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Returns the row and byte column of `byte`, as tree-sitter counts them.
+fn byte_to_point(data: &Rope, byte: usize) -> Point {
+    let row = data.byte_to_line(byte);
+    Point::new(row, byte - data.line_to_byte(row))
+}
+
+/// Returns the number of characters of `line` without its line break.
+fn line_len(data: &Rope, line: usize) -> usize {
+    let text = data.line(line);
+    let line_break = match text.len_chars() {
+        0 => 0,
+        len if text.char(len - 1) != '\n' => 0,
+        len if len > 1 && text.char(len - 2) == '\r' => 2,
+        _ => 1,
+    };
+    text.len_chars() - line_break
+}
+
+/// Returns whether `line` holds nothing but whitespace.
+fn is_blank(data: &Rope, line: usize) -> bool {
+    data.line(line).chars().all(char::is_whitespace)
+}
+
+/// Returns the character ranges of the words of `line`, relative to its start. Words are split
+/// at Unicode word boundaries, with runs of punctuation such as `->` kept together. With `big`,
+/// words are runs of anything but whitespace instead.
+fn words(line: &str, big: bool) -> Vec<Range<usize>> {
+    let mut words: Vec<(Range<usize>, bool)> = vec![];
+    let mut start = 0;
+    for segment in line.split_word_bounds() {
+        let len = segment.chars().count();
+        let range = start..start + len;
+        start += len;
+        if segment.chars().all(char::is_whitespace) {
+            continue;
+        }
+        let word = segment.chars().any(|c| c.is_alphanumeric() || c == '_');
+        match words.last_mut() {
+            Some((last, last_word)) if last.end == range.start && (big || !word && !*last_word) => {
+                last.end = range.end;
+            }
+            _ => words.push((range, word)),
+        }
+    }
+    words.into_iter().map(|(range, _)| range).collect()
+}
+
+/// Returns the first word from the line of `from` onwards that `accept` returns true for, as a
+/// range of characters in `data`.
+fn next_word<F>(data: &Rope, from: usize, big: bool, accept: F) -> Option<Range<usize>>
+where
+    F: Fn(&Range<usize>) -> bool,
+{
+    (data.char_to_line(from)..data.len_lines()).find_map(|line| {
+        let start = data.line_to_char(line);
+        words(&data.line(line).to_string(), big)
+            .into_iter()
+            .map(|word| start + word.start..start + word.end)
+            .find(|word| accept(word))
+    })
+}
+
+/// Returns the last word up to the line of `from` that `accept` returns true for, as a range of
+/// characters in `data`.
+fn prev_word<F>(data: &Rope, from: usize, big: bool, accept: F) -> Option<Range<usize>>
+where
+    F: Fn(&Range<usize>) -> bool,
+{
+    (0..=data.char_to_line(from)).rev().find_map(|line| {
+        let start = data.line_to_char(line);
+        words(&data.line(line).to_string(), big)
+            .into_iter()
+            .rev()
+            .map(|word| start + word.start..start + word.end)
+            .find(|word| accept(word))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a buffer without a language holding `text`, with `selections` the first of them
+    /// being the primary one.
+    fn buffer(text: &str, selections: Selections) -> Buffer {
+        let mut buffer = Buffer::new(String::from(text), None);
+        buffer.selections = selections;
+        buffer
+    }
+
+    /// Returns a buffer without a language holding `text`, with a single selection on the
+    /// character at `position`.
+    fn cursor(text: &str, position: usize) -> Buffer {
+        selected(text, position..position + 1)
+    }
+
+    /// Returns a buffer without a language holding `text`, with `selection` as its only
+    /// selection.
+    fn selected(text: &str, selection: Selection) -> Buffer {
+        let mut buffer = Buffer::new(String::from(text), None);
+        buffer.selections[0] = selection;
+        buffer
+    }
+
+    #[test]
+    fn words_keep_runs_of_punctuation_together() {
+        assert_eq!(
+            words("foo->bar  baz_1", false),
+            vec![0..3, 3..5, 5..8, 10..15]
+        );
+        assert_eq!(words("foo->bar  baz_1", true), vec![0..8, 10..15]);
+    }
+
+    #[test]
+    fn word_motions_move_between_words_across_lines() {
+        let mut buffer = cursor("foo bar\nbaz", 0);
+        buffer.select_next_word_start(false, false);
+        assert_eq!(buffer.get_root_selection(), 4..5);
+        buffer.select_next_word_start(false, false);
+        assert_eq!(buffer.get_root_selection(), 8..9);
+        buffer.select_prev_word_start(false, false);
+        assert_eq!(buffer.get_root_selection(), 4..5);
+        buffer.select_next_word_end(false, false);
+        assert_eq!(buffer.get_root_selection(), 6..7);
+        buffer.select_next_word_end(false, false);
+        assert_eq!(buffer.get_root_selection(), 10..11);
+    }
+
+    #[test]
+    fn word_motions_count_characters_rather_than_bytes() {
+        let mut buffer = cursor("héllo wörld", 0);
+        buffer.select_next_word_start(false, false);
+        assert_eq!(buffer.get_root_selection(), 6..7);
+        buffer.select_next_word_end(false, false);
+        assert_eq!(buffer.get_root_selection(), 10..11);
+    }
+
+    #[test]
+    fn extending_word_motions_grow_the_selection() {
+        let mut buffer = cursor("foo bar baz", 4);
+        buffer.select_next_word_start(false, true);
+        assert_eq!(buffer.get_root_selection(), 4..9);
+
+        let mut buffer = cursor("foo bar baz", 8);
+        buffer.select_prev_word_start(false, true);
+        assert_eq!(buffer.get_root_selection(), 4..9);
+    }
+
+    #[test]
+    fn line_motions_stop_at_the_edges_of_the_line() {
+        let mut buffer = cursor("  foo bar\nbaz", 6);
+        buffer.select_line_start(false);
+        assert_eq!(buffer.get_root_selection(), 0..1);
+        buffer.select_line_first_non_blank(false);
+        assert_eq!(buffer.get_root_selection(), 2..3);
+        buffer.select_line_end(false);
+        assert_eq!(buffer.get_root_selection(), 8..9);
+    }
+
+    #[test]
+    fn paragraph_motions_move_to_the_blank_lines_between_paragraphs() {
+        let mut buffer = cursor("a\nb\n\nc\nd\n\ne", 0);
+        buffer.select_next_paragraph(false);
+        assert_eq!(buffer.get_root_selection(), 4..5);
+        buffer.select_next_paragraph(false);
+        assert_eq!(buffer.get_root_selection(), 9..10);
+        buffer.select_next_paragraph(false);
+        assert_eq!(buffer.get_root_selection(), 10..11);
+        buffer.select_prev_paragraph(false);
+        assert_eq!(buffer.get_root_selection(), 9..10);
+        buffer.select_prev_paragraph(false);
+        assert_eq!(buffer.get_root_selection(), 4..5);
+        buffer.select_prev_paragraph(false);
+        assert_eq!(buffer.get_root_selection(), 0..1);
+    }
+
+    #[test]
+    fn document_end_is_the_start_of_the_last_line_with_text() {
+        let mut buffer = cursor("a\nbc\n", 0);
+        buffer.select_document_end(false);
+        assert_eq!(buffer.get_root_selection(), 2..3);
+        buffer.select_document_start(true);
+        assert_eq!(buffer.get_root_selection(), 0..3);
+    }
+
+    #[test]
+    fn newline_keeps_the_order_and_widths_of_the_selections() {
+        let mut buffer = buffer("abc def\nghi jkl\n", vec![12..15, 0..1]);
+        buffer.insert_newline();
+        assert_eq!(buffer.data.to_string(), "\nabc def\nghi\njkl\n");
+        assert_eq!(buffer.selections, vec![13..16, 1..2]);
+    }
+
+    #[test]
+    fn typed_char_keeps_the_order_and_widths_of_the_selections() {
+        let mut buffer = buffer("abc def\nghi jkl\n", vec![12..15, 0..1]);
+        buffer.insert_char('x');
+        assert_eq!(buffer.data.to_string(), "xabc def\nghi xjkl\n");
+        assert_eq!(buffer.selections, vec![14..17, 1..2]);
+    }
+
+    #[test]
+    fn backspace_merges_the_deletions_of_neighbouring_cursors() {
+        let mut pair = buffer("a()b\n", vec![2..3, 3..4]);
+        pair.delete_char_backward();
+        assert_eq!(pair.data.to_string(), "ab\n");
+        assert_eq!(pair.selections, vec![1..2]);
+
+        let mut plain = buffer("abc\n", vec![1..2, 2..3]);
+        plain.delete_char_backward();
+        assert_eq!(plain.data.to_string(), "c\n");
+        assert_eq!(plain.selections, vec![0..1]);
+    }
+
+    /// Compiles `pattern`, which has to be valid.
+    fn regex(pattern: &str) -> RopeRegex {
+        RopeRegex::new(pattern).unwrap()
+    }
+
+    #[test]
+    fn empty_matches_are_not_selected() {
+        let mut select = selected("axxbx\n", 0..5);
+        assert!(!select.select_matches(&regex("^")));
+        assert_eq!(select.selections, vec![0..5]);
+        assert!(select.select_matches(&regex("x*")));
+        assert_eq!(select.selections, vec![1..3, 4..5]);
+
+        let mut split = selected("axxbx\n", 0..5);
+        assert!(split.split_selections(&regex("x*")));
+        assert_eq!(split.selections, vec![0..1, 3..4]);
+    }
+
+    #[test]
+    fn matches_must_lie_inside_the_selection() {
+        let mut before_end = selected("foo bar foo\n", 0..9);
+        assert!(before_end.select_matches(&regex("foo")));
+        assert_eq!(before_end.selections, vec![0..3]);
+
+        let mut after_start = selected("foo bar foo\n", 1..11);
+        assert!(after_start.select_matches(&regex("foo")));
+        assert_eq!(after_start.selections, vec![8..11]);
+    }
+
+    #[test]
+    fn splitting_on_a_match_at_the_edge_leaves_no_empty_piece() {
+        let mut at_start = selected("foo bar\n", 0..7);
+        assert!(at_start.split_selections(&regex("foo ")));
+        assert_eq!(at_start.selections, vec![4..7]);
+
+        let mut at_end = selected("foo bar\n", 0..7);
+        assert!(at_end.split_selections(&regex(" bar")));
+        assert_eq!(at_end.selections, vec![0..3]);
+
+        let mut whole = selected("foo bar\n", 4..7);
+        assert!(!whole.split_selections(&regex("bar")));
+        assert_eq!(whole.selections, vec![4..7]);
+    }
+
+    #[test]
+    fn lines_keep_their_line_endings() {
+        let mut lines = buffer("ab\ncd\nef\n", vec![4..9, 1..3]);
+        lines.split_selections_into_lines();
+        assert_eq!(lines.selections, vec![4..6, 6..9, 1..3]);
+
+        let mut line_break = selected("ab\ncd\n", 2..3);
+        line_break.split_selections_into_lines();
+        assert_eq!(line_break.selections, vec![2..3]);
+    }
+
+    #[test]
+    fn keeping_nothing_leaves_the_selections_alone() {
+        let mut buffer = buffer("foo bar\n", vec![4..7, 0..3]);
+        assert!(!buffer.keep_matching_selections(&regex("z"), true));
+        assert_eq!(buffer.selections, vec![4..7, 0..3]);
+        assert!(!buffer.keep_matching_selections(&regex("[a-z]"), false));
+        assert_eq!(buffer.selections, vec![4..7, 0..3]);
+    }
+
+    #[test]
+    fn removing_nothing_keeps_the_primary_selection_first() {
+        let mut buffer = buffer("foo bar\n", vec![4..7, 0..3]);
+        assert!(buffer.keep_matching_selections(&regex("z"), false));
+        assert_eq!(buffer.selections, vec![4..7, 0..3]);
+        assert!(buffer.keep_matching_selections(&regex("o"), true));
+        assert_eq!(buffer.selections, vec![0..3]);
+    }
+
+    #[test]
+    fn overlapping_and_touching_selections_are_merged() {
+        let mut chained = buffer("abcdefghij\n", vec![5..6, 0..2, 1..4, 8..9, 4..5]);
+        chained.merge_selections();
+        assert_eq!(chained.selections, vec![0..6, 8..9]);
+
+        let mut primary = buffer("abcdefghij\n", vec![8..10, 0..2, 7..9]);
+        primary.merge_selections();
+        assert_eq!(primary.selections, vec![7..10, 0..2]);
+    }
+
+    /// Types `'` at the end of the Rust code `before` and returns the resulting line.
+    fn type_quote(before: &str) -> String {
+        let mut buffer = Buffer::new(format!("{}\n", before), language_by_name("rust"));
+        let end = before.chars().count();
+        buffer.selections[0] = end..end + 1;
+        buffer.insert_char('\'');
+        buffer.data.to_string().trim_end().to_string()
+    }
+
+    #[test]
+    fn quote_is_paired_where_a_char_literal_goes() {
+        assert_eq!(type_quote("let c = "), "let c = ''");
+        assert_eq!(type_quote("let b = c < "), "let b = c < ''");
+        assert_eq!(type_quote("f('a', "), "f('a', ''");
+    }
+
+    #[test]
+    fn quote_is_not_paired_where_a_lifetime_goes() {
+        assert_eq!(type_quote("fn f(s: &"), "fn f(s: &'");
+        assert_eq!(type_quote("struct Foo<"), "struct Foo<'");
+        assert_eq!(type_quote("struct Foo<'a, "), "struct Foo<'a, '");
+        assert_eq!(type_quote("fn f<T: "), "fn f<T: '");
+        assert_eq!(type_quote("loop { break "), "loop { break '");
+    }
+
+    /// Returns a search for `target` in the given direction.
+    fn char_search(target: char, forward: bool, till: bool) -> CharSearch {
+        CharSearch {
+            target,
+            forward,
+            till,
+        }
+    }
+
+    #[test]
+    fn find_moves_onto_the_next_occurrence() {
+        let mut buffer = cursor("a,b,c;d", 0);
+        buffer.select_char_search(char_search(',', true, false), false, false, false);
+        assert_eq!(buffer.get_root_selection(), 1..2);
+        buffer.select_char_search(char_search(',', true, false), true, false, false);
+        assert_eq!(buffer.get_root_selection(), 3..4);
+        buffer.select_char_search(char_search('a', false, false), true, false, false);
+        assert_eq!(buffer.get_root_selection(), 0..1);
+    }
+
+    #[test]
+    fn till_stops_next_to_the_occurrence_and_skips_it_when_repeated() {
+        let mut buffer = cursor("a,b,c;d", 0);
+        buffer.select_char_search(char_search(',', true, true), false, false, false);
+        assert_eq!(buffer.get_root_selection(), 0..1);
+        buffer.select_char_search(char_search(',', true, true), true, false, false);
+        assert_eq!(buffer.get_root_selection(), 2..3);
+
+        let mut buffer = cursor("a,b,c;d", 6);
+        buffer.select_char_search(char_search(',', false, true), false, false, false);
+        assert_eq!(buffer.get_root_selection(), 4..5);
+        buffer.select_char_search(char_search(',', false, true), true, false, false);
+        assert_eq!(buffer.get_root_selection(), 2..3);
+    }
+
+    #[test]
+    fn char_search_stays_on_the_line_unless_asked_otherwise() {
+        let mut buffer = cursor("ab\ncd", 0);
+        buffer.select_char_search(char_search('c', true, false), false, false, false);
+        assert_eq!(buffer.get_root_selection(), 0..1);
+        buffer.select_char_search(char_search('c', true, false), false, true, false);
+        assert_eq!(buffer.get_root_selection(), 3..4);
+    }
+
+    #[test]
+    fn extending_char_search_grows_the_selection() {
+        let mut buffer = cursor("fn main(ü: u8)", 0);
+        buffer.select_char_search(char_search(':', true, false), false, false, true);
+        assert_eq!(buffer.get_root_selection(), 0..10);
+    }
 }
@@ -0,0 +1,711 @@
+use super::modes::Modes;
+use crate::input::{Key, KeyCode, Modifiers};
+use std::collections::HashMap;
+use std::fmt;
+
+/// An action keys can be bound to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    NoOp,
+    Quit,
+    InsertMode,
+    Prompt,
+    SelectMode,
+    NormalMode,
+    MoveCharLeft,
+    MoveCharDown,
+    MoveCharUp,
+    MoveCharRight,
+    NextWordStart,
+    NextLongWordStart,
+    PrevWordStart,
+    PrevLongWordStart,
+    NextWordEnd,
+    NextLongWordEnd,
+    LineStart,
+    LineFirstNonBlank,
+    LineEnd,
+    NextParagraph,
+    PrevParagraph,
+    DocumentStart,
+    DocumentEnd,
+    FindNextChar,
+    TillNextChar,
+    FindPrevChar,
+    TillPrevChar,
+    RepeatCharSearch,
+    ReverseCharSearch,
+    ExpandSelection,
+    ShrinkSelection,
+    SelectNextSibling,
+    SelectPrevSibling,
+    ExtendToNextSibling,
+    ExtendToPrevSibling,
+    MatchBracket,
+    NextError,
+    PrevError,
+    NextFunction,
+    PrevFunction,
+    NextClass,
+    PrevClass,
+    ContextStart,
+    SelectFunctionInside,
+    SelectFunctionAround,
+    SelectClassInside,
+    SelectClassAround,
+    SelectParameterInside,
+    SelectParameterAround,
+    SelectCommentInside,
+    SelectCommentAround,
+    SelectBlockInside,
+    SelectBlockAround,
+    OpenFold,
+    CloseFold,
+    ToggleFold,
+    OpenAllFolds,
+    CloseAllFolds,
+    Reindent,
+    ToggleComments,
+    ScrollDown,
+    ScrollUp,
+    InsertNewline,
+    InsertIndent,
+    DeleteCharBackward,
+    SearchForward,
+    SearchBackward,
+    SearchNext,
+    SearchPrev,
+    Undo,
+    Redo,
+    SelectRegex,
+    SplitRegex,
+    KeepMatching,
+    RemoveMatching,
+    SplitLines,
+    MergeSelections,
+    KeepPrimarySelection,
+    /// A command line run as if it was typed after `:`, such as `symbols`.
+    Typed(String),
+}
+
+/// The name keys are bound to and the description shown for each command.
+#[rustfmt::skip]
+static COMMANDS: &[(Command, &str, &str)] = &[
+    (Command::NoOp, "no_op", "Do nothing"),
+    (Command::Quit, "quit", "Quit"),
+    (Command::InsertMode, "insert_mode", "Insert text"),
+    (Command::Prompt, "command_mode", "Enter a command"),
+    (Command::SelectMode, "select_mode", "Extend selections with motions"),
+    (Command::NormalMode, "normal_mode", "Return to normal mode"),
+    (Command::MoveCharLeft, "move_char_left", "Move left"),
+    (Command::MoveCharDown, "move_char_down", "Move down"),
+    (Command::MoveCharUp, "move_char_up", "Move up"),
+    (Command::MoveCharRight, "move_char_right", "Move right"),
+    (Command::NextWordStart, "next_word_start", "Next word start"),
+    (Command::NextLongWordStart, "next_long_word_start", "Next WORD start"),
+    (Command::PrevWordStart, "prev_word_start", "Previous word start"),
+    (Command::PrevLongWordStart, "prev_long_word_start", "Previous WORD start"),
+    (Command::NextWordEnd, "next_word_end", "Next word end"),
+    (Command::NextLongWordEnd, "next_long_word_end", "Next WORD end"),
+    (Command::LineStart, "line_start", "Line start"),
+    (Command::LineFirstNonBlank, "line_first_non_blank", "First non-blank of the line"),
+    (Command::LineEnd, "line_end", "Line end"),
+    (Command::NextParagraph, "next_paragraph", "Next paragraph"),
+    (Command::PrevParagraph, "prev_paragraph", "Previous paragraph"),
+    (Command::DocumentStart, "document_start", "First line, or line [count]"),
+    (Command::DocumentEnd, "document_end", "Last line, or line [count]"),
+    (Command::FindNextChar, "find_next_char", "Find the next character"),
+    (Command::TillNextChar, "till_next_char", "Till the next character"),
+    (Command::FindPrevChar, "find_prev_char", "Find the previous character"),
+    (Command::TillPrevChar, "till_prev_char", "Till the previous character"),
+    (Command::RepeatCharSearch, "repeat_char_search", "Repeat the character search"),
+    (Command::ReverseCharSearch, "reverse_char_search", "Repeat the character search backwards"),
+    (Command::ExpandSelection, "expand_selection", "Select the parent node"),
+    (Command::ShrinkSelection, "shrink_selection", "Select the child node"),
+    (Command::SelectNextSibling, "select_next_sibling", "Select the next sibling node"),
+    (Command::SelectPrevSibling, "select_prev_sibling", "Select the previous sibling node"),
+    (Command::ExtendToNextSibling, "extend_to_next_sibling", "Extend to the next sibling node"),
+    (Command::ExtendToPrevSibling, "extend_to_prev_sibling", "Extend to the previous sibling node"),
+    (Command::MatchBracket, "match_bracket", "Matching bracket"),
+    (Command::NextError, "next_error", "Next syntax error"),
+    (Command::PrevError, "prev_error", "Previous syntax error"),
+    (Command::NextFunction, "next_function", "Next function"),
+    (Command::PrevFunction, "prev_function", "Previous function"),
+    (Command::NextClass, "next_class", "Next type"),
+    (Command::PrevClass, "prev_class", "Previous type"),
+    (Command::ContextStart, "context_start", "Start of the enclosing scope"),
+    (Command::SelectFunctionInside, "select_function_inside", "Function body"),
+    (Command::SelectFunctionAround, "select_function_around", "Function"),
+    (Command::SelectClassInside, "select_class_inside", "Type body"),
+    (Command::SelectClassAround, "select_class_around", "Type"),
+    (Command::SelectParameterInside, "select_parameter_inside", "Parameter"),
+    (Command::SelectParameterAround, "select_parameter_around", "Parameter and separator"),
+    (Command::SelectCommentInside, "select_comment_inside", "Comment text"),
+    (Command::SelectCommentAround, "select_comment_around", "Comment"),
+    (Command::SelectBlockInside, "select_block_inside", "Block contents"),
+    (Command::SelectBlockAround, "select_block_around", "Block"),
+    (Command::OpenFold, "open_fold", "Open fold"),
+    (Command::CloseFold, "close_fold", "Close fold"),
+    (Command::ToggleFold, "toggle_fold", "Toggle fold"),
+    (Command::OpenAllFolds, "open_all_folds", "Open all folds"),
+    (Command::CloseAllFolds, "close_all_folds", "Close all folds"),
+    (Command::Reindent, "reindent", "Reindent lines"),
+    (Command::ToggleComments, "toggle_comments", "Toggle comments"),
+    (Command::ScrollDown, "scroll_down", "Scroll down"),
+    (Command::ScrollUp, "scroll_up", "Scroll up"),
+    (Command::InsertNewline, "insert_newline", "Insert a new line"),
+    (Command::InsertIndent, "insert_indent", "Insert indentation"),
+    (Command::DeleteCharBackward, "delete_char_backward", "Delete the previous character"),
+    (Command::SearchForward, "search_forward", "Search forward"),
+    (Command::SearchBackward, "search_backward", "Search backward"),
+    (Command::SearchNext, "search_next", "Next match of the search"),
+    (Command::SearchPrev, "search_prev", "Previous match of the search"),
+    (Command::Undo, "undo", "Undo"),
+    (Command::Redo, "redo", "Redo"),
+    (Command::SelectRegex, "select_regex", "Select regex matches in the selections"),
+    (Command::SplitRegex, "split_regex", "Split the selections on a regex"),
+    (Command::KeepMatching, "keep_matching", "Keep the selections matching a regex"),
+    (Command::RemoveMatching, "remove_matching", "Remove the selections matching a regex"),
+    (Command::SplitLines, "split_lines", "Split the selections into lines"),
+    (Command::MergeSelections, "merge_selections", "Merge overlapping and touching selections"),
+    (Command::KeepPrimarySelection, "keep_primary_selection", "Keep only the primary selection"),
+];
+
+impl Command {
+    /// Returns the command called `name`. Names starting with `:` are typed commands.
+    pub fn from_name(name: &str) -> Option<Command> {
+        if let Some(line) = name.strip_prefix(':') {
+            return Some(Command::Typed(line.to_string()));
+        }
+        COMMANDS
+            .iter()
+            .find(|(_, command_name, _)| *command_name == name)
+            .map(|(command, _, _)| command.clone())
+    }
+
+    pub fn description(&self) -> &str {
+        match self {
+            Command::Typed(line) => line,
+            _ => COMMANDS
+                .iter()
+                .find(|(command, _, _)| command == self)
+                .map_or("", |(_, _, description)| description),
+        }
+    }
+
+    /// Whether the command takes the key typed after it as an argument, like `f` does.
+    pub fn takes_char(&self) -> bool {
+        matches!(
+            self,
+            Command::FindNextChar
+                | Command::TillNextChar
+                | Command::FindPrevChar
+                | Command::TillPrevChar
+        )
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Typed(line) => write!(f, ":{}", line),
+            _ => {
+                let name = COMMANDS
+                    .iter()
+                    .find(|(command, _, _)| command == self)
+                    .map_or("", |(_, name, _)| name);
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+const NORMAL_BINDINGS: &[(&str, &str)] = &[
+    ("q", "quit"),
+    ("i", "insert_mode"),
+    (":", "command_mode"),
+    ("v", "select_mode"),
+    ("h", "move_char_left"),
+    ("j", "move_char_down"),
+    ("k", "move_char_up"),
+    ("l", "move_char_right"),
+    ("w", "next_word_start"),
+    ("W", "next_long_word_start"),
+    ("b", "prev_word_start"),
+    ("B", "prev_long_word_start"),
+    ("e", "next_word_end"),
+    ("E", "next_long_word_end"),
+    ("0", "line_start"),
+    ("^", "line_first_non_blank"),
+    ("$", "line_end"),
+    ("}", "next_paragraph"),
+    ("{", "prev_paragraph"),
+    ("gg", "document_start"),
+    ("G", "document_end"),
+    ("f", "find_next_char"),
+    ("t", "till_next_char"),
+    ("F", "find_prev_char"),
+    ("T", "till_prev_char"),
+    (";", "repeat_char_search"),
+    (",", "reverse_char_search"),
+    ("<A-o>", "expand_selection"),
+    ("<A-i>", "shrink_selection"),
+    ("<A-n>", "select_next_sibling"),
+    ("<A-p>", "select_prev_sibling"),
+    ("<A-N>", "extend_to_next_sibling"),
+    ("<A-P>", "extend_to_prev_sibling"),
+    ("%", "match_bracket"),
+    ("]e", "next_error"),
+    ("[e", "prev_error"),
+    ("]f", "next_function"),
+    ("[f", "prev_function"),
+    ("]t", "next_class"),
+    ("[t", "prev_class"),
+    ("[c", "context_start"),
+    ("mif", "select_function_inside"),
+    ("maf", "select_function_around"),
+    ("mit", "select_class_inside"),
+    ("mat", "select_class_around"),
+    ("mia", "select_parameter_inside"),
+    ("maa", "select_parameter_around"),
+    ("mic", "select_comment_inside"),
+    ("mac", "select_comment_around"),
+    ("mib", "select_block_inside"),
+    ("mab", "select_block_around"),
+    ("zo", "open_fold"),
+    ("zc", "close_fold"),
+    ("za", "toggle_fold"),
+    ("zR", "open_all_folds"),
+    ("zM", "close_all_folds"),
+    ("=", "reindent"),
+    ("gc", "toggle_comments"),
+    ("<C-e>", "scroll_down"),
+    ("<C-y>", "scroll_up"),
+    ("/", "search_forward"),
+    ("?", "search_backward"),
+    ("n", "search_next"),
+    ("N", "search_prev"),
+    ("u", "undo"),
+    ("U", "redo"),
+    ("s", "select_regex"),
+    ("S", "split_regex"),
+    ("<A-k>", "keep_matching"),
+    ("<A-K>", "remove_matching"),
+    ("<A-s>", "split_lines"),
+    ("<A-_>", "merge_selections"),
+    ("<A-,>", "keep_primary_selection"),
+    ("<space>s", ":symbols"),
+    ("<space>o", ":outline"),
+];
+
+/// Bound on top of the normal mode bindings.
+const SELECT_BINDINGS: &[(&str, &str)] = &[("<esc>", "normal_mode"), ("v", "normal_mode")];
+
+const INSERT_BINDINGS: &[(&str, &str)] = &[
+    ("<esc>", "normal_mode"),
+    ("<ret>", "insert_newline"),
+    ("<tab>", "insert_indent"),
+    ("<bs>", "delete_char_backward"),
+];
+
+/// A node of a keymap. The keys leading to it are bound to its command, and are a prefix of the
+/// sequences bound below it.
+#[derive(Clone, Default)]
+pub struct KeyTrie {
+    pub command: Option<Command>,
+    /// The nodes reached by the keys that can follow, in the order they were bound.
+    pub children: Vec<(Key, KeyTrie)>,
+}
+
+impl KeyTrie {
+    /// Returns the node `keys` lead to.
+    pub fn get(&self, keys: &[Key]) -> Option<&KeyTrie> {
+        keys.iter().try_fold(self, |node, key| {
+            node.children
+                .iter()
+                .find(|(child_key, _)| child_key == key)
+                .map(|(_, child)| child)
+        })
+    }
+
+    fn bind(&mut self, keys: &[Key], command: Command) {
+        let node = keys.iter().fold(self, |node, key| {
+            let index = match node
+                .children
+                .iter()
+                .position(|(child_key, _)| child_key == key)
+            {
+                Some(index) => index,
+                None => {
+                    node.children.push((*key, KeyTrie::default()));
+                    node.children.len() - 1
+                }
+            };
+            &mut node.children[index].1
+        });
+        node.command = Some(command);
+    }
+
+    /// Whether the keys leading to this node are both bound and the prefix of longer sequences,
+    /// so only a timeout tells which one was meant.
+    pub fn is_ambiguous(&self) -> bool {
+        self.command.is_some() && !self.children.is_empty()
+    }
+
+    /// Returns every bound sequence below this node with its command, depth first.
+    pub fn bindings(&self) -> Vec<(Vec<Key>, &Command)> {
+        let mut bindings = vec![];
+        self.collect_bindings(&mut vec![], &mut bindings);
+        bindings
+    }
+
+    fn collect_bindings<'a>(
+        &'a self,
+        keys: &mut Vec<Key>,
+        bindings: &mut Vec<(Vec<Key>, &'a Command)>,
+    ) {
+        if let Some(command) = &self.command {
+            bindings.push((keys.clone(), command));
+        }
+        for (key, child) in &self.children {
+            keys.push(*key);
+            child.collect_bindings(keys, bindings);
+            keys.pop();
+        }
+    }
+}
+
+/// The key bindings of the modes that run commands from keys.
+pub struct Keymap {
+    normal: KeyTrie,
+    select: KeyTrie,
+    insert: KeyTrie,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        let mut keymap = Keymap {
+            normal: KeyTrie::default(),
+            select: KeyTrie::default(),
+            insert: KeyTrie::default(),
+        };
+        let defaults = [
+            ("normal", NORMAL_BINDINGS),
+            ("select", SELECT_BINDINGS),
+            ("insert", INSERT_BINDINGS),
+        ];
+        for (mode, bindings) in defaults.iter() {
+            for (keys, command) in bindings.iter() {
+                keymap.bind(mode, keys, command).unwrap();
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Returns the keymap of `mode`, or `None` for the modes that take text instead of commands.
+    pub fn for_mode(&self, mode: Modes) -> Option<&KeyTrie> {
+        match mode {
+            Modes::Normal => Some(&self.normal),
+            Modes::Select => Some(&self.select),
+            Modes::Insert => Some(&self.insert),
+            Modes::Command | Modes::Picker | Modes::Search | Modes::Confirm => None,
+        }
+    }
+
+    /// Returns the keymap called `mode`, as written in the config file.
+    pub fn by_name(&self, mode: &str) -> Option<&KeyTrie> {
+        match mode {
+            "normal" => Some(&self.normal),
+            "select" => Some(&self.select),
+            "insert" => Some(&self.insert),
+            _ => None,
+        }
+    }
+
+    /// Binds the sequence written as `keys` in `mode` to the command called `command`. As select
+    /// mode works like normal mode, normal mode bindings are made in both.
+    pub fn bind(&mut self, mode: &str, keys: &str, command: &str) -> Result<(), String> {
+        let keys = parse_keys(keys)?;
+        let command =
+            Command::from_name(command).ok_or_else(|| format!("unknown command: {}", command))?;
+        match mode {
+            "normal" => {
+                self.normal.bind(&keys, command.clone());
+                self.select.bind(&keys, command);
+            }
+            "select" => self.select.bind(&keys, command),
+            "insert" => self.insert.bind(&keys, command),
+            _ => return Err(format!("unknown key mode: {}", mode)),
+        }
+        Ok(())
+    }
+
+    /// Applies the `[keys.<mode>]` tables of the config on top of the defaults. Normal mode goes
+    /// first so that select mode can override what it inherits from it.
+    pub fn override_with(
+        &mut self,
+        bindings: &HashMap<String, Vec<(String, String)>>,
+    ) -> Vec<String> {
+        let mut modes: Vec<&String> = bindings.keys().collect();
+        modes.sort_by_key(|mode| mode.as_str() != "normal");
+        let mut errors = vec![];
+        for mode in modes {
+            for (keys, command) in &bindings[mode] {
+                if let Err(err) = self.bind(mode, keys, command) {
+                    errors.push(format!("{} ({})", err, keys));
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// Parses a key sequence such as `gg`, `<space>fs` or `<C-S-e>`.
+pub fn parse_keys(notation: &str) -> Result<Vec<Key>, String> {
+    let mut keys = vec![];
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = rest
+                .find('>')
+                .ok_or_else(|| format!("unclosed key name: {}", notation))?;
+            keys.push(parse_key_name(&rest[1..end])?);
+            rest = &rest[end + 1..];
+        } else {
+            keys.push(Key::new(KeyCode::Char(c)));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    if keys.is_empty() {
+        return Err(String::from("empty key sequence"));
+    }
+    Ok(keys)
+}
+
+/// Parses what is between the angle brackets of a key such as `<esc>` or `<C-A-o>`. Modifiers
+/// are written as `C-` for control, `A-` for alt, `S-` for shift and `D-` for super.
+fn parse_key_name(name: &str) -> Result<Key, String> {
+    let mut modifiers = Modifiers::NONE;
+    let mut rest = name;
+    loop {
+        let modifier = match rest.as_bytes() {
+            [b'C', b'-', _, ..] => &mut modifiers.ctrl,
+            [b'A', b'-', _, ..] => &mut modifiers.alt,
+            [b'S', b'-', _, ..] => &mut modifiers.shift,
+            [b'D', b'-', _, ..] => &mut modifiers.super_key,
+            _ => break,
+        };
+        *modifier = true;
+        rest = &rest[2..];
+    }
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_lowercase().as_str() {
+            "space" => KeyCode::Char(' '),
+            "lt" => KeyCode::Char('<'),
+            "ret" | "enter" => KeyCode::Enter,
+            "tab" => KeyCode::Tab,
+            "esc" => KeyCode::Esc,
+            "bs" | "backspace" => KeyCode::Backspace,
+            "del" => KeyCode::Delete,
+            "ins" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            key => match key.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("unknown key: <{}>", name)),
+            },
+        },
+    };
+    Ok(Key::with_modifiers(code, modifiers))
+}
+
+/// Writes `key` the way `parse_keys` reads it.
+pub fn key_name(key: &Key) -> String {
+    let name = match key.code {
+        KeyCode::Char(' ') => String::from("space"),
+        KeyCode::Char('<') => String::from("lt"),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Enter => String::from("ret"),
+        KeyCode::Tab => String::from("tab"),
+        KeyCode::Backspace => String::from("bs"),
+        KeyCode::Esc => String::from("esc"),
+        KeyCode::Delete => String::from("del"),
+        KeyCode::Insert => String::from("ins"),
+        KeyCode::Up => String::from("up"),
+        KeyCode::Down => String::from("down"),
+        KeyCode::Left => String::from("left"),
+        KeyCode::Right => String::from("right"),
+        KeyCode::Home => String::from("home"),
+        KeyCode::End => String::from("end"),
+        KeyCode::PageUp => String::from("pageup"),
+        KeyCode::PageDown => String::from("pagedown"),
+        KeyCode::F(n) => format!("F{}", n),
+    };
+    let modifiers = key.modifiers;
+    let prefix: String = [
+        (modifiers.ctrl, "C-"),
+        (modifiers.alt, "A-"),
+        (modifiers.shift, "S-"),
+        (modifiers.super_key, "D-"),
+    ]
+    .iter()
+    .filter(|(held, _)| *held)
+    .map(|(_, prefix)| *prefix)
+    .collect();
+    if prefix.is_empty() && name.chars().count() == 1 {
+        name
+    } else {
+        format!("<{}{}>", prefix, name)
+    }
+}
+
+/// Writes a key sequence the way `parse_keys` reads it.
+pub fn keys_name(keys: &[Key]) -> String {
+    keys.iter().map(key_name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn char_key(c: char) -> Key {
+        Key::new(KeyCode::Char(c))
+    }
+
+    #[test]
+    fn plain_characters_are_keys_of_their_own() {
+        assert_eq!(parse_keys("gg"), Ok(vec![char_key('g'), char_key('g')]));
+        assert_eq!(parse_keys("é"), Ok(vec![char_key('é')]));
+    }
+
+    #[test]
+    fn names_in_angle_brackets_are_special_keys() {
+        assert_eq!(
+            parse_keys("<space>fs"),
+            Ok(vec![char_key(' '), char_key('f'), char_key('s')])
+        );
+        assert_eq!(parse_keys("<lt>"), Ok(vec![char_key('<')]));
+        assert_eq!(parse_keys("<Ret>"), Ok(vec![Key::new(KeyCode::Enter)]));
+        assert_eq!(parse_keys("<f5>"), Ok(vec![Key::new(KeyCode::F(5))]));
+    }
+
+    #[test]
+    fn modifiers_prefix_the_key_name() {
+        let ctrl_alt_o = Key::with_modifiers(
+            KeyCode::Char('o'),
+            Modifiers {
+                ctrl: true,
+                alt: true,
+                ..Modifiers::NONE
+            },
+        );
+        assert_eq!(parse_keys("<C-A-o>"), Ok(vec![ctrl_alt_o]));
+        assert_eq!(parse_keys("<S-a>"), parse_keys("A"));
+        assert_eq!(parse_keys("<C-->"), Ok(vec![Key::ctrl('-')]));
+    }
+
+    #[test]
+    fn malformed_sequences_are_errors() {
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys("<esc").is_err());
+        assert!(parse_keys("<nope>").is_err());
+        assert!(parse_keys("<F13>").is_err());
+    }
+
+    #[test]
+    fn key_names_parse_back_to_the_same_keys() {
+        for notation in [
+            "gg",
+            "<space>fs",
+            "<C-e>",
+            "<A-K>",
+            "<lt>",
+            "<ret>",
+            "<F5>",
+            "<C-S-up>",
+        ] {
+            let keys = parse_keys(notation).unwrap();
+            assert_eq!(parse_keys(&keys_name(&keys)), Ok(keys));
+        }
+        assert_eq!(keys_name(&parse_keys("<C-S-e>").unwrap()), "<C-E>");
+    }
+
+    #[test]
+    fn normal_mode_bindings_are_made_in_select_mode_too() {
+        let mut keymap = Keymap::default();
+        keymap.bind("normal", "<space>x", "no_op").unwrap();
+        let keys = parse_keys("<space>x").unwrap();
+        for mode in [Modes::Normal, Modes::Select] {
+            let node = keymap.for_mode(mode).and_then(|trie| trie.get(&keys));
+            assert_eq!(
+                node.and_then(|node| node.command.clone()),
+                Some(Command::NoOp)
+            );
+        }
+        assert!(keymap.for_mode(Modes::Insert).unwrap().get(&keys).is_none());
+    }
+
+    #[test]
+    fn unknown_commands_and_modes_are_errors() {
+        let mut keymap = Keymap::default();
+        assert!(keymap.bind("normal", "x", "no_such_command").is_err());
+        assert!(keymap.bind("visual", "x", "no_op").is_err());
+        assert_eq!(
+            Command::from_name(":w"),
+            Some(Command::Typed(String::from("w")))
+        );
+    }
+
+    #[test]
+    fn select_mode_overrides_apply_after_normal_mode_ones() {
+        let mut keymap = Keymap::default();
+        let mut bindings = HashMap::new();
+        bindings.insert(
+            String::from("select"),
+            vec![(String::from("z"), String::from("quit"))],
+        );
+        bindings.insert(
+            String::from("normal"),
+            vec![(String::from("z"), String::from("no_op"))],
+        );
+        assert!(keymap.override_with(&bindings).is_empty());
+        let keys = parse_keys("z").unwrap();
+        let command = |mode| {
+            keymap
+                .for_mode(mode)
+                .unwrap()
+                .get(&keys)
+                .unwrap()
+                .command
+                .clone()
+        };
+        assert_eq!(command(Modes::Normal), Some(Command::NoOp));
+        assert_eq!(command(Modes::Select), Some(Command::Quit));
+    }
+
+    #[test]
+    fn prefixes_that_are_bound_are_ambiguous() {
+        let mut keymap = Keymap::default();
+        keymap.bind("insert", "j", "no_op").unwrap();
+        keymap.bind("insert", "jk", "normal_mode").unwrap();
+        let insert = keymap.for_mode(Modes::Insert).unwrap();
+        assert!(insert
+            .get(&parse_keys("j").unwrap())
+            .unwrap()
+            .is_ambiguous());
+        assert!(!insert
+            .get(&parse_keys("jk").unwrap())
+            .unwrap()
+            .is_ambiguous());
+    }
+}
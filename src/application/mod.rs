@@ -1,96 +1,997 @@
 pub mod buffer;
+pub mod keymap;
 pub mod modes;
+pub mod picker;
+pub mod search;
+pub mod substitute;
 
-use log::{debug};
+use crate::config::Config;
+use crate::input::{read_events, Event, Key, KeyCode};
+use crate::rope_regex::RopeRegex;
+use crate::theme::Theme;
 use crate::view::terminal::Terminal;
 use crate::view::traits::View;
-use buffer::Buffer;
+use buffer::{Buffer, CharSearch};
+use keymap::{key_name, keys_name, Command, KeyTrie, Keymap};
+use log::debug;
 use modes::Modes;
-use std::fs;
+use picker::{Picker, PickerItem};
+use search::{next_match, RegexAction, Search};
 use std::io::stdin;
-use termion::event::Key;
-use termion::input::TermRead;
+use std::ops::Range;
+use std::path::Path;
+use std::thread::{spawn, JoinHandle};
+use substitute::{match_count, Confirmation, Substitution, Target};
+use termion::event::{MouseButton, MouseEvent};
 use tokio::sync::mpsc;
-use std::thread::{JoinHandle, spawn};
+use tokio::time::{self, Instant};
 
 /// `Application` handles the logic of the application and is responsible for managing state.
 pub struct Application {
     quit: bool,
     mode: Modes,
     command: String,
+    /// The keys typed so far of a sequence bound in the keymap.
+    pending_keys: Vec<Key>,
     view: Terminal,
     buffer: Buffer,
+    /// The open buffers other than the current one.
+    buffers: Vec<Buffer>,
+    picker: Option<Picker>,
+    config: Config,
+    keymap: Keymap,
+    /// The command whose keys were typed, waiting for the character it takes.
+    awaiting_char: Option<Command>,
+    /// The count typed before a normal mode command, such as the `5` of `5j`.
+    count: Option<usize>,
+    search: Search,
+    /// The substitution whose replacements are being confirmed.
+    confirmation: Option<Confirmation>,
+    /// The last `f`, `t`, `F` or `T` search, repeated by `;` and `,`.
+    last_char_search: Option<CharSearch>,
 }
 
 impl Application {
     pub fn new() -> Application {
+        let path = Path::new("src/view/terminal.rs");
+        let (config, config_error) = match Config::load() {
+            Ok(config) => (config, None),
+            Err(err) => (Config::default(), Some(err)),
+        };
+        let mut view = Terminal::new(&config);
+        if let Some(name) = &config.theme {
+            match Theme::load(name) {
+                Ok(theme) => view.set_theme(theme),
+                Err(err) => view.show_message(err.to_string()),
+            }
+        }
+        let mut keymap = Keymap::default();
+        let key_errors = keymap.override_with(&config.keys);
+        if !key_errors.is_empty() {
+            view.show_message(format!("invalid key bindings: {}", key_errors.join(", ")));
+        }
+        if let Some(err) = config_error {
+            view.show_message(err.to_string());
+        }
+        let mut buffer = Buffer::from_file(path).unwrap();
+        buffer.indent = config.indent(buffer.language);
         Application {
             quit: false,
             mode: Modes::Normal,
             command: String::from(""),
-            view: Terminal::new(),
-            buffer: Buffer::new(fs::read_to_string("src/view/terminal.rs").unwrap()),
+            pending_keys: vec![],
+            view,
+            buffer,
+            buffers: vec![],
+            picker: None,
+            config,
+            keymap,
+            awaiting_char: None,
+            count: None,
+            search: Search::new(),
+            confirmation: None,
+            last_char_search: None,
         }
     }
 
     fn handle_command(&mut self) {
         debug!("Executing command: {}", self.command.as_str());
-        match self.command.as_str() {
-            "q" => self.quit = true,
-            _ => self.change_mode(Modes::Normal),
+        let command = std::mem::take(&mut self.command);
+        self.change_mode(Modes::Normal);
+        let current = self.buffer.get_root_selection_line();
+        let last = self.buffer.data.len_lines() - 1;
+        if let Some(substitution) = Substitution::parse(&command, current, last) {
+            match substitution {
+                Ok(substitution) => self.substitute(substitution),
+                Err(err) => self.view.show_message(err),
+            }
+            return;
+        }
+        let mut args = command.split_whitespace();
+        match args.next() {
+            Some("q") => self.quit = true,
+            Some("theme") => self.handle_theme_command(args.next()),
+            Some("e") => match args.next() {
+                Some(path) => self.edit(Path::new(path)),
+                None => self.view.show_message(String::from("Usage: e <path>")),
+            },
+            Some("bn") => self.next_buffer(),
+            Some("bp") => self.prev_buffer(),
+            Some("symbols") => self.open_symbols_picker(),
+            Some("outline") => self.view.toggle_outline(),
+            Some("map") => self.show_keymap(args.next().unwrap_or("normal")),
+            Some(name) => self.view.show_message(format!("Unknown command: {}", name)),
+            None => {}
+        }
+    }
+
+    /// Switches to the named theme, or lists the available themes when no name is given.
+    fn handle_theme_command(&mut self, name: Option<&str>) {
+        match name {
+            Some(name) => match Theme::load(name) {
+                Ok(theme) => self.view.set_theme(theme),
+                Err(err) => self.view.show_message(err.to_string()),
+            },
+            None => self.view.show_popup(Theme::available()),
+        }
+    }
+
+    /// Lists the key bindings of the mode called `mode` with their commands.
+    fn show_keymap(&mut self, mode: &str) {
+        let keymap = match self.keymap.by_name(mode) {
+            Some(keymap) => keymap,
+            None => {
+                self.view
+                    .show_message(format!("Unknown key mode: {}", mode));
+                return;
+            }
+        };
+        let bindings: Vec<(String, String, &str)> = keymap
+            .bindings()
+            .into_iter()
+            .map(|(keys, command)| (keys_name(&keys), command.to_string(), command.description()))
+            .collect();
+        let keys_width = bindings
+            .iter()
+            .map(|(keys, _, _)| keys.chars().count())
+            .max()
+            .unwrap_or(0);
+        let command_width = bindings
+            .iter()
+            .map(|(_, name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let lines = bindings
+            .iter()
+            .map(|(keys, name, description)| {
+                format!(
+                    "{:<kw$}  {:<cw$}  {}",
+                    keys,
+                    name,
+                    description,
+                    kw = keys_width,
+                    cw = command_width
+                )
+            })
+            .collect();
+        self.view.show_popup(lines);
+    }
+
+    fn open_symbols_picker(&mut self) {
+        let items = self
+            .buffer
+            .symbols()
+            .into_iter()
+            .map(|symbol| PickerItem {
+                label: format!("{} ({})", symbol.name, symbol.kind),
+                position: symbol.name_start,
+            })
+            .collect();
+        self.picker = Some(Picker::new("symbols", items));
+        self.change_mode(Modes::Picker);
+        self.update_picker_view();
+    }
+
+    fn update_picker_view(&mut self) {
+        match &self.picker {
+            Some(picker) => {
+                let labels = picker
+                    .matches()
+                    .iter()
+                    .map(|item| item.label.clone())
+                    .collect();
+                self.view
+                    .show_picker(&picker.title, picker.query(), labels, picker.selected());
+            }
+            None => self.view.hide_picker(),
         }
     }
 
-    fn handle_insert_mode_event(&mut self, event: termion::event::Key) {
-        match event {
-            Key::Esc => self.view.change_mode(Modes::Normal),
-            Key::Char(c) => self.view.write_char(c),
+    fn handle_picker_mode_event(&mut self, event: Key) {
+        let picker = match &mut self.picker {
+            Some(picker) => picker,
+            None => return,
+        };
+        match event.code {
+            KeyCode::Esc => {
+                self.picker = None;
+                self.change_mode(Modes::Normal);
+            }
+            KeyCode::Enter => {
+                if let Some(item) = picker.current() {
+                    self.buffer.select_at(item.position);
+                }
+                self.picker = None;
+                self.change_mode(Modes::Normal);
+            }
+            KeyCode::Backspace => picker.pop(),
+            KeyCode::Up => picker.move_selection(-1),
+            KeyCode::Down => picker.move_selection(1),
+            KeyCode::Char('p') if event.modifiers.ctrl => picker.move_selection(-1),
+            KeyCode::Char('n') if event.modifiers.ctrl => picker.move_selection(1),
+            KeyCode::Char(c) if event.char().is_some() => picker.push(c),
             _ => {}
         }
+        self.update_picker_view();
+    }
+
+    /// Makes the buffer of `path` the current one, opening the file if it is not open yet.
+    fn edit(&mut self, path: &Path) {
+        if self.buffer.path.as_deref() == Some(path) {
+            return;
+        }
+        let buffer = match self
+            .buffers
+            .iter()
+            .position(|buffer| buffer.path.as_deref() == Some(path))
+        {
+            Some(index) => self.buffers.remove(index),
+            None => match Buffer::from_file(path) {
+                Ok(mut buffer) => {
+                    buffer.indent = self.config.indent(buffer.language);
+                    buffer
+                }
+                Err(err) => {
+                    self.view
+                        .show_message(format!("{}: {}", path.display(), err));
+                    return;
+                }
+            },
+        };
+        let previous = std::mem::replace(&mut self.buffer, buffer);
+        self.buffers.push(previous);
+    }
+
+    fn next_buffer(&mut self) {
+        if !self.buffers.is_empty() {
+            let next = self.buffers.remove(0);
+            let previous = std::mem::replace(&mut self.buffer, next);
+            self.buffers.push(previous);
+        }
+    }
+
+    fn prev_buffer(&mut self) {
+        if let Some(prev) = self.buffers.pop() {
+            let previous = std::mem::replace(&mut self.buffer, prev);
+            self.buffers.insert(0, previous);
+        }
     }
 
     fn change_mode(&mut self, mode: Modes) {
+        // What is typed in one go is undone in one go.
+        match (self.mode, mode) {
+            (Modes::Insert, Modes::Insert) => {}
+            (_, Modes::Insert) => self.buffer.begin_transaction(),
+            (Modes::Insert, _) => self.buffer.end_transaction(),
+            _ => {}
+        }
         self.mode = mode;
         self.view.change_mode(mode);
     }
 
-    fn handle_normal_mode_event(&mut self, event: termion::event::Key) {
-        match event {
-            Key::Char('q') => self.quit = true,
-            Key::Char('i') => self.change_mode(Modes::Insert),
-            Key::Char(':') => self.change_mode(Modes::Command),
+    /// Handles a key in the modes whose keys are looked up in the keymap. Keys are collected in
+    /// `pending_keys` until they make up a bound sequence.
+    fn handle_mapped_event(&mut self, key: Key) {
+        if let Some(command) = self.awaiting_char.take() {
+            if let Some(c) = key.char() {
+                self.run_char_command(&command, c);
+            }
+            self.finish_keys();
+            return;
+        }
+        if self.pending_keys.is_empty() && !matches!(self.mode, Modes::Insert) {
+            if let Some(c) = key.char() {
+                if let Some(digit) = c.to_digit(10) {
+                    // A leading `0` is the line start motion rather than part of a count.
+                    if digit > 0 || self.count.is_some() {
+                        let count = self.count.unwrap_or(0);
+                        self.count = Some(count.saturating_mul(10).saturating_add(digit as usize));
+                        return;
+                    }
+                }
+            }
+        }
+        self.pending_keys.push(key);
+        let node = self
+            .keymap
+            .for_mode(self.mode)
+            .and_then(|keymap| keymap.get(&self.pending_keys));
+        match node {
+            Some(node) if node.children.is_empty() => {
+                let command = node.command.clone();
+                self.run_pending_command(command);
+            }
+            // More keys may follow, or the timeout runs the command of an ambiguous sequence.
+            Some(_) => {}
+            None => self.handle_unbound_keys(),
+        }
+    }
+
+    /// Runs the command the pending keys are bound to, unless it still needs its argument.
+    fn run_pending_command(&mut self, command: Option<Command>) {
+        if let Some(command) = command {
+            self.run_command(command);
+        }
+        if self.awaiting_char.is_none() {
+            self.finish_keys();
+        }
+    }
+
+    /// Forgets the keys and count of a finished sequence.
+    fn finish_keys(&mut self) {
+        self.pending_keys.clear();
+        self.count = None;
+    }
+
+    /// Handles pending keys that are neither bound nor the start of a bound sequence.
+    fn handle_unbound_keys(&mut self) {
+        let mut keys = std::mem::take(&mut self.pending_keys);
+        if let Modes::Insert = self.mode {
+            // The keys before the last one are text after all, and the last one may start
+            // another sequence.
+            let last = keys.pop().unwrap();
+            if keys.is_empty() {
+                if let Some(c) = last.char() {
+                    self.buffer.insert_char(c);
+                }
+            } else {
+                self.insert_keys(&keys);
+                self.handle_mapped_event(last);
+            }
+            return;
+        }
+        // Escape cancels a sequence.
+        if keys.last().map(|key| key.code) != Some(KeyCode::Esc) {
+            self.view
+                .show_message(format!("Unknown key sequence: {}", keys_name(&keys)));
+        }
+        self.count = None;
+    }
+
+    /// Inserts the characters typed as `keys`.
+    fn insert_keys(&mut self, keys: &[Key]) {
+        for key in keys {
+            if let Some(c) = key.char() {
+                self.buffer.insert_char(c);
+            }
+        }
+    }
+
+    /// Returns whether the pending keys only wait for a timeout to be told apart from a longer
+    /// sequence: they are bound themselves, or they may be text in insert mode.
+    fn waits_for_timeout(&self) -> bool {
+        if self.awaiting_char.is_some() {
+            return false;
+        }
+        match self
+            .keymap
+            .for_mode(self.mode)
+            .and_then(|keymap| keymap.get(&self.pending_keys))
+        {
+            Some(node) if !self.pending_keys.is_empty() => {
+                node.is_ambiguous() || matches!(self.mode, Modes::Insert)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the keymap node of the pending keys when more keys are needed to complete them
+    /// in normal or select mode.
+    fn pending_prefix(&self) -> Option<&KeyTrie> {
+        if self.awaiting_char.is_some()
+            || self.pending_keys.is_empty()
+            || matches!(self.mode, Modes::Insert)
+        {
+            return None;
+        }
+        self.keymap
+            .for_mode(self.mode)?
+            .get(&self.pending_keys)
+            .filter(|node| !node.children.is_empty())
+    }
+
+    /// Lists the keys that can follow the pending ones with what they do.
+    fn show_which_key(&mut self) {
+        let node = match self.pending_prefix() {
+            Some(node) => node,
+            None => return,
+        };
+        let keys: Vec<String> = node.children.iter().map(|(key, _)| key_name(key)).collect();
+        let width = keys
+            .iter()
+            .map(|key| key.chars().count())
+            .max()
+            .unwrap_or(0);
+        let lines = node
+            .children
+            .iter()
+            .zip(&keys)
+            .map(|((_, child), key)| {
+                let description = match &child.command {
+                    Some(command) => command.description().to_string(),
+                    None => format!("+{} more", child.bindings().len()),
+                };
+                format!("{:<w$}  {}", key, description, w = width)
+            })
+            .collect();
+        self.view.show_popup(lines);
+    }
+
+    /// Runs the command of the pending keys once no longer sequence was typed in time.
+    fn handle_key_timeout(&mut self) {
+        self.view.clear_messages();
+        let command = self
+            .keymap
+            .for_mode(self.mode)
+            .and_then(|keymap| keymap.get(&self.pending_keys))
+            .and_then(|node| node.command.clone());
+        match command {
+            Some(command) => self.run_pending_command(Some(command)),
+            None => {
+                let keys = std::mem::take(&mut self.pending_keys);
+                self.insert_keys(&keys);
+            }
+        }
+        self.show_pending_keys();
+        self.buffer.reveal_selection();
+    }
+
+    /// Runs `command` with the pending count, extending the selections in select mode.
+    fn run_command(&mut self, command: Command) {
+        if command.takes_char() {
+            self.awaiting_char = Some(command);
+            return;
+        }
+        let extend = matches!(self.mode, Modes::Select);
+        match command {
+            Command::NoOp => {}
+            Command::Quit => self.quit = true,
+            Command::InsertMode => self.change_mode(Modes::Insert),
+            Command::Prompt => self.change_mode(Modes::Command),
+            Command::SelectMode => self.change_mode(Modes::Select),
+            Command::NormalMode => self.change_mode(Modes::Normal),
             // Selections
-            Key::Char('h') => self.buffer.select_char_left(),
-            Key::Char('j') => self.buffer.select_char_down(),
-            Key::Char('k') => self.buffer.select_char_up(),
-            Key::Char('l') => self.buffer.select_char_right(),
+            Command::MoveCharLeft => self.repeat(Buffer::select_char_left),
+            Command::MoveCharDown => self.repeat(Buffer::select_char_down),
+            Command::MoveCharUp => self.repeat(Buffer::select_char_up),
+            Command::MoveCharRight => self.repeat(Buffer::select_char_right),
+            Command::NextWordStart => {
+                self.repeat(|buffer| buffer.select_next_word_start(false, extend))
+            }
+            Command::NextLongWordStart => {
+                self.repeat(|buffer| buffer.select_next_word_start(true, extend))
+            }
+            Command::PrevWordStart => {
+                self.repeat(|buffer| buffer.select_prev_word_start(false, extend))
+            }
+            Command::PrevLongWordStart => {
+                self.repeat(|buffer| buffer.select_prev_word_start(true, extend))
+            }
+            Command::NextWordEnd => {
+                self.repeat(|buffer| buffer.select_next_word_end(false, extend))
+            }
+            Command::NextLongWordEnd => {
+                self.repeat(|buffer| buffer.select_next_word_end(true, extend))
+            }
+            Command::LineStart => self.buffer.select_line_start(extend),
+            Command::LineFirstNonBlank => self.buffer.select_line_first_non_blank(extend),
+            Command::LineEnd => self.buffer.select_line_end(extend),
+            Command::NextParagraph => self.repeat(|buffer| buffer.select_next_paragraph(extend)),
+            Command::PrevParagraph => self.repeat(|buffer| buffer.select_prev_paragraph(extend)),
+            Command::DocumentStart => match self.count {
+                Some(line) => self.buffer.select_line(line - 1, extend),
+                None => self.buffer.select_document_start(extend),
+            },
+            Command::DocumentEnd => match self.count {
+                Some(line) => self.buffer.select_line(line - 1, extend),
+                None => self.buffer.select_document_end(extend),
+            },
+            Command::RepeatCharSearch => self.repeat_char_search(false),
+            Command::ReverseCharSearch => self.repeat_char_search(true),
+            Command::ExpandSelection => self.repeat(Buffer::expand_selections),
+            Command::ShrinkSelection => self.repeat(Buffer::shrink_selections),
+            Command::SelectNextSibling => self.repeat(|buffer| buffer.select_sibling(true)),
+            Command::SelectPrevSibling => self.repeat(|buffer| buffer.select_sibling(false)),
+            Command::ExtendToNextSibling => self.repeat(|buffer| buffer.extend_to_sibling(true)),
+            Command::ExtendToPrevSibling => self.repeat(|buffer| buffer.extend_to_sibling(false)),
+            Command::MatchBracket => self.buffer.select_matching_bracket(),
+            Command::NextError => self.repeat(Buffer::select_next_error),
+            Command::PrevError => self.repeat(Buffer::select_prev_error),
+            Command::NextFunction => {
+                self.repeat(|buffer| buffer.select_next_textobject("function.around"))
+            }
+            Command::PrevFunction => {
+                self.repeat(|buffer| buffer.select_prev_textobject("function.around"))
+            }
+            Command::NextClass => {
+                self.repeat(|buffer| buffer.select_next_textobject("class.around"))
+            }
+            Command::PrevClass => {
+                self.repeat(|buffer| buffer.select_prev_textobject("class.around"))
+            }
+            Command::ContextStart => self.buffer.select_context_start(),
+            Command::SelectFunctionInside => self.select_textobject("function.inside"),
+            Command::SelectFunctionAround => self.select_textobject("function.around"),
+            Command::SelectClassInside => self.select_textobject("class.inside"),
+            Command::SelectClassAround => self.select_textobject("class.around"),
+            Command::SelectParameterInside => self.select_textobject("parameter.inside"),
+            Command::SelectParameterAround => self.select_textobject("parameter.around"),
+            Command::SelectCommentInside => self.select_textobject("comment.inside"),
+            Command::SelectCommentAround => self.select_textobject("comment.around"),
+            Command::SelectBlockInside => self.select_textobject("block.inside"),
+            Command::SelectBlockAround => self.select_textobject("block.around"),
+            // Folds
+            Command::OpenFold => self.buffer.open_fold(),
+            Command::CloseFold => self.buffer.close_fold(),
+            Command::ToggleFold => self.buffer.toggle_fold(),
+            Command::OpenAllFolds => self.buffer.open_all_folds(),
+            Command::CloseAllFolds => self.buffer.close_all_folds(),
+            // Changes
+            Command::Reindent => self.buffer.reindent_selections(self.count.unwrap_or(1)),
+            Command::ToggleComments => self.buffer.toggle_comments(self.count.unwrap_or(1)),
+            Command::InsertNewline => self.buffer.insert_newline(),
+            Command::InsertIndent => self.buffer.insert_indent(),
+            Command::DeleteCharBackward => self.buffer.delete_char_backward(),
             // Scrolling
-            Key::Ctrl('e') => self.view.scroll_down(&mut self.buffer),
-            Key::Ctrl('y') => self.view.scroll_up(&mut self.buffer),
+            Command::ScrollDown => {
+                for _ in 0..self.count.unwrap_or(1) {
+                    self.view.scroll_down(&mut self.buffer);
+                }
+            }
+            Command::ScrollUp => {
+                for _ in 0..self.count.unwrap_or(1) {
+                    self.view.scroll_up(&mut self.buffer);
+                }
+            }
+            // Search
+            Command::SearchForward => self.start_search(true),
+            Command::SearchBackward => self.start_search(false),
+            Command::SearchNext => self.search_next(false),
+            Command::SearchPrev => self.search_next(true),
+            // Multiple selections
+            Command::SelectRegex => self.start_regex_prompt(RegexAction::Select),
+            Command::SplitRegex => self.start_regex_prompt(RegexAction::Split),
+            Command::KeepMatching => self.start_regex_prompt(RegexAction::Keep),
+            Command::RemoveMatching => self.start_regex_prompt(RegexAction::Remove),
+            Command::SplitLines => self.buffer.split_selections_into_lines(),
+            Command::MergeSelections => self.buffer.merge_selections(),
+            Command::KeepPrimarySelection => self.buffer.keep_primary_selection(),
+            // History
+            Command::Undo => {
+                for _ in 0..self.count.unwrap_or(1) {
+                    if !self.buffer.undo() {
+                        self.view
+                            .show_message(String::from("Already at the oldest change"));
+                        break;
+                    }
+                }
+            }
+            Command::Redo => {
+                for _ in 0..self.count.unwrap_or(1) {
+                    if !self.buffer.redo() {
+                        self.view
+                            .show_message(String::from("Already at the newest change"));
+                        break;
+                    }
+                }
+            }
+            Command::Typed(line) => {
+                self.command = line;
+                self.handle_command();
+            }
+            Command::FindNextChar
+            | Command::TillNextChar
+            | Command::FindPrevChar
+            | Command::TillPrevChar => {}
+        }
+    }
+
+    /// Runs a command that takes the character typed after its keys, like `f` does.
+    fn run_char_command(&mut self, command: &Command, c: char) {
+        let extend = matches!(self.mode, Modes::Select);
+        let search = CharSearch {
+            target: c,
+            forward: matches!(command, Command::FindNextChar | Command::TillNextChar),
+            till: matches!(command, Command::TillNextChar | Command::TillPrevChar),
+        };
+        self.last_char_search = Some(search);
+        let across_lines = self.config.find_across_lines;
+        self.buffer
+            .select_char_search(search, false, across_lines, extend);
+        // Further occurrences are found the way `;` finds them.
+        for _ in 1..self.count.unwrap_or(1) {
+            self.buffer
+                .select_char_search(search, true, across_lines, extend);
+        }
+    }
+
+    /// Runs `action` on the buffer as many times as the pending count says.
+    fn repeat<F>(&mut self, action: F)
+    where
+        F: Fn(&mut Buffer),
+    {
+        for _ in 0..self.count.unwrap_or(1) {
+            action(&mut self.buffer);
+        }
+    }
+
+    /// Repeats the last character search, in the opposite direction with `reverse`.
+    fn repeat_char_search(&mut self, reverse: bool) {
+        let extend = matches!(self.mode, Modes::Select);
+        let across_lines = self.config.find_across_lines;
+        if let Some(mut search) = self.last_char_search {
+            search.forward ^= reverse;
+            self.repeat(|buffer| buffer.select_char_search(search, true, across_lines, extend));
+        }
+    }
+
+    /// Selects the text object called `name`, such as `function.inside`.
+    fn select_textobject(&mut self, name: &str) {
+        self.repeat(|buffer| buffer.select_textobject(name));
+    }
+
+    fn start_search(&mut self, forward: bool) {
+        self.search.forward = forward;
+        self.start_regex_prompt(RegexAction::Search);
+    }
+
+    /// Prompts for a pattern to use as `action` says.
+    fn start_regex_prompt(&mut self, action: RegexAction) {
+        self.search
+            .start(action, self.buffer.selections.clone(), self.mode);
+        self.change_mode(Modes::Search);
+        self.preview_search();
+    }
+
+    /// Shows what the query being typed would do. A search highlights its matches and selects
+    /// the nearest one in its direction from where it started, the other actions change the
+    /// selections they started from.
+    fn preview_search(&mut self) {
+        self.buffer.selections = self.search.origin.clone();
+        let regex = if self.search.query.is_empty() {
+            None
+        } else {
+            RopeRegex::new(&self.search.query).ok()
+        };
+        let mut matches = vec![];
+        let mut current = None;
+        match (self.search.action, regex) {
+            (RegexAction::Search, Some(regex)) => {
+                matches = regex.find_all(&self.buffer.data, 0..self.buffer.data.len_chars());
+                let forward = self.search.forward;
+                current = next_match(&matches, self.search_position(forward), forward, false)
+                    .map(|(index, _)| index);
+                if let Some(index) = current {
+                    let extend = matches!(self.search.mode, Modes::Select);
+                    self.buffer.select_range(matches[index].clone(), extend);
+                }
+            }
+            (action, Some(regex)) => {
+                self.select_with_regex(action, &regex);
+            }
+            (_, None) => {}
+        }
+        let prompt = self.search.action.prompt(self.search.forward);
+        self.view
+            .show_search(prompt, &self.search.query, matches, current);
+    }
+
+    /// Changes the selections with the matches of `regex` as `action` says. Returns whether it
+    /// did, which it does not when no selection would be left.
+    fn select_with_regex(&mut self, action: RegexAction, regex: &RopeRegex) -> bool {
+        match action {
+            // Searches move the primary selection instead, see `preview_search`.
+            RegexAction::Search => false,
+            RegexAction::Select => self.buffer.select_matches(regex),
+            RegexAction::Split => self.buffer.split_selections(regex),
+            RegexAction::Keep => self.buffer.keep_matching_selections(regex, true),
+            RegexAction::Remove => self.buffer.keep_matching_selections(regex, false),
+        }
+    }
+
+    /// Returns where a search in the given direction starts from: the end of the primary
+    /// selection going forward, its start going backward.
+    fn search_position(&self, forward: bool) -> usize {
+        let primary = &self.buffer.selections[0];
+        if forward {
+            primary.end.saturating_sub(1)
+        } else {
+            primary.start
+        }
+    }
+
+    fn handle_search_mode_event(&mut self, event: Key) {
+        match event.code {
+            KeyCode::Esc => {
+                self.buffer.selections = std::mem::take(&mut self.search.origin);
+                self.finish_search();
+                return;
+            }
+            KeyCode::Enter => {
+                self.buffer.selections = std::mem::take(&mut self.search.origin);
+                let confirmed = self.search.confirm();
+                self.finish_search();
+                match (confirmed, self.search.action) {
+                    (Ok(regex), RegexAction::Search) => {
+                        self.search.last = Some(regex);
+                        self.search_next(false);
+                    }
+                    (Ok(regex), action) => {
+                        if !self.select_with_regex(action, &regex) {
+                            self.view.show_message(String::from("Nothing selected"));
+                        }
+                    }
+                    (Err(err), _) => self.view.show_message(format!("Invalid pattern: {}", err)),
+                }
+                return;
+            }
+            KeyCode::Backspace => self.search.pop(),
+            KeyCode::Up => self.search.history_prev(),
+            KeyCode::Down => self.search.history_next(),
+            KeyCode::Char(c) if event.char().is_some() => self.search.push(c),
+            _ => return,
+        }
+        self.preview_search();
+    }
+
+    fn finish_search(&mut self) {
+        self.view.hide_search();
+        self.change_mode(self.search.mode);
+    }
+
+    /// Selects the next match of the last search, or the previous one with `reverse`, as many
+    /// times as the count says, and shows which of the matches it is.
+    fn search_next(&mut self, reverse: bool) {
+        let regex = match &self.search.last {
+            Some(regex) => regex,
+            None => {
+                self.view.show_message(String::from("No search to repeat"));
+                return;
+            }
+        };
+        let matches = regex.find_all(&self.buffer.data, 0..self.buffer.data.len_chars());
+        let forward = self.search.forward != reverse;
+        let extend = matches!(self.mode, Modes::Select);
+        let mut current = None;
+        let mut wrapped = false;
+        for _ in 0..self.count.unwrap_or(1) {
+            match next_match(&matches, self.search_position(forward), forward, false) {
+                Some((index, wrap)) => {
+                    self.buffer.select_range(matches[index].clone(), extend);
+                    current = Some(index);
+                    wrapped |= wrap;
+                }
+                None => break,
+            }
+        }
+        let pattern = format!(
+            "{}{}",
+            RegexAction::Search.prompt(self.search.forward),
+            regex.as_str()
+        );
+        let message = match current {
+            Some(index) if wrapped => {
+                format!("{} [{}/{}] wrapped", pattern, index + 1, matches.len())
+            }
+            Some(index) => format!("{} [{}/{}]", pattern, index + 1, matches.len()),
+            None => format!("Pattern not found: {}", regex.as_str()),
+        };
+        self.view.show_message(message);
+    }
+
+    /// Replaces the matches of a substitution in its target, all at once or one at a time when
+    /// they are to be confirmed.
+    fn substitute(&mut self, substitution: Substitution) {
+        let pattern = if substitution.pattern.is_empty() {
+            match &self.search.last {
+                Some(regex) => regex.as_str().to_string(),
+                None => {
+                    self.view.show_message(String::from("No previous pattern"));
+                    return;
+                }
+            }
+        } else {
+            substitution.pattern.clone()
+        };
+        let regex = match substitution.ignore_case {
+            Some(ignore_case) => RopeRegex::with_case(&pattern, ignore_case),
+            None => RopeRegex::new(&pattern),
+        };
+        let regex = match regex {
+            Ok(regex) => regex,
+            Err(err) => {
+                self.view.show_message(format!("Invalid pattern: {}", err));
+                return;
+            }
+        };
+        let data = &self.buffer.data;
+        let mut scopes = match &substitution.target {
+            Target::SelectedLines => self
+                .buffer
+                .selections
+                .iter()
+                .map(|selection| {
+                    let first = data.char_to_line(selection.start);
+                    let last = data.char_to_line(selection.end.max(selection.start + 1) - 1);
+                    self.buffer.line_chars(first..last + 1)
+                })
+                .collect(),
+            Target::Selections => self.buffer.selections.clone(),
+            Target::All => vec![self.buffer.line_chars(0..data.len_lines())],
+            Target::Lines(lines) => vec![self.buffer.line_chars(lines.clone())],
+        };
+        scopes.sort_by_key(|scope| scope.start);
+        scopes.dedup();
+        let mut matches: Vec<Range<usize>> = vec![];
+        for scope in scopes {
+            for m in regex.find_all(data, scope.clone()) {
+                let overlaps = matches.last().is_some_and(|last| m.start < last.end);
+                if m.end <= scope.end && !overlaps {
+                    matches.push(m);
+                }
+            }
+        }
+        if !substitution.global {
+            // Only the first match of each line is replaced.
+            matches.dedup_by_key(|m| data.char_to_line(m.start));
+        }
+        let changes: Vec<(Range<usize>, String)> = matches
+            .into_iter()
+            .map(|m| {
+                let groups = regex.captures(data, m.clone());
+                (m, substitution.replacement.expand(&groups))
+            })
+            .collect();
+        if changes.is_empty() {
+            self.view
+                .show_message(format!("Pattern not found: {}", pattern));
+        } else if substitution.confirm {
+            self.buffer.begin_transaction();
+            self.confirmation = Some(Confirmation::new(changes));
+            self.change_mode(Modes::Confirm);
+            self.show_confirmation();
+        } else {
+            self.buffer.replace(&changes);
+            self.view
+                .show_message(format!("Replaced {}", match_count(changes.len())));
+        }
+    }
+
+    /// Selects the match to confirm the replacement of, or finishes the substitution when there
+    /// are none left.
+    fn show_confirmation(&mut self) {
+        let current = self
+            .confirmation
+            .as_ref()
+            .and_then(|confirmation| confirmation.current().cloned());
+        match current {
+            Some((range, text)) => {
+                self.buffer.select_range(range, false);
+                self.view.show_message(format!(
+                    "Replace with {}? (y/n/a/l/q)",
+                    text.replace('\n', "\\n")
+                ));
+            }
+            None => self.finish_confirmation(),
+        }
+    }
+
+    fn handle_confirm_mode_event(&mut self, event: Key) {
+        let confirmation = match &mut self.confirmation {
+            Some(confirmation) => confirmation,
+            None => return,
+        };
+        let change = match confirmation.current() {
+            Some(change) => change.clone(),
+            None => return,
+        };
+        match (event.code, event.char()) {
+            (_, Some('y')) => {
+                self.buffer.replace(&[change]);
+                confirmation.next(true);
+            }
+            (_, Some('n')) => confirmation.next(false),
+            (_, Some('a')) => {
+                let rest = confirmation.take_rest();
+                self.buffer.replace(&rest);
+            }
+            (_, Some('l')) => {
+                self.buffer.replace(&[change]);
+                confirmation.next(true);
+                confirmation.stop();
+            }
+            (KeyCode::Esc, _) | (_, Some('q')) => confirmation.stop(),
             _ => {}
         }
+        self.show_confirmation();
     }
 
-    fn handle_command_mode_event(&mut self, event: termion::event::Key) {
-        match event {
-            Key::Backspace => {
+    fn finish_confirmation(&mut self) {
+        if let Some(confirmation) = self.confirmation.take() {
+            self.buffer.end_transaction();
+            self.change_mode(Modes::Normal);
+            self.view
+                .show_message(format!("Replaced {}", match_count(confirmation.replaced)));
+        }
+    }
+
+    fn handle_command_mode_event(&mut self, event: Key) {
+        match event.code {
+            KeyCode::Backspace => {
                 self.command.pop();
             }
-            Key::Char('\n') => self.handle_command(),
-            Key::Char(c) => self.command.push(c),
-            Key::Esc => self.change_mode(Modes::Normal),
+            KeyCode::Enter => self.handle_command(),
+            KeyCode::Char(c) if event.char().is_some() => self.command.push(c),
+            KeyCode::Esc => {
+                self.command.clear();
+                self.change_mode(Modes::Normal);
+            }
             _ => {}
         }
     }
 
-    fn handle_event(&mut self, event: termion::event::Key) {
-        match self.mode {
-            Modes::Insert => self.handle_insert_mode_event(event),
-            Modes::Normal => self.handle_normal_mode_event(event),
-            Modes::Command => self.handle_command_mode_event(event),
+    fn handle_mouse_event(&mut self, event: MouseEvent) {
+        if let MouseEvent::Press(MouseButton::Left, col, row) = event {
+            // Mouse positions are 1-based, but a malformed report can still decode to 0.
+            if col == 0 || row == 0 {
+                return;
+            }
+            let position = self
+                .view
+                .position_at(&self.buffer, row as usize - 1, col as usize - 1);
+            if let Some(position) = position {
+                self.buffer.select_at(position);
+            }
         }
     }
 
+    fn handle_event(&mut self, event: Event) {
+        self.view.clear_messages();
+        match (self.mode, event) {
+            (Modes::Insert, Event::Key(key))
+            | (Modes::Normal, Event::Key(key))
+            | (Modes::Select, Event::Key(key)) => self.handle_mapped_event(key),
+            (Modes::Command, Event::Key(key)) => self.handle_command_mode_event(key),
+            (Modes::Picker, Event::Key(key)) => self.handle_picker_mode_event(key),
+            (Modes::Search, Event::Key(key)) => self.handle_search_mode_event(key),
+            (Modes::Confirm, Event::Key(key)) => self.handle_confirm_mode_event(key),
+            (Modes::Normal, Event::Mouse(mouse)) => self.handle_mouse_event(mouse),
+            (_, Event::KeyboardProtocol) => self.view.enable_keyboard_protocol(),
+            _ => {}
+        }
+        self.show_pending_keys();
+        self.buffer.reveal_selection();
+    }
+
+    fn show_pending_keys(&mut self) {
+        let count = self
+            .count
+            .map(|count| count.to_string())
+            .unwrap_or_default();
+        self.view
+            .show_pending_keys(&format!("{}{}", count, keys_name(&self.pending_keys)));
+    }
+
     pub async fn start(mut self) {
         debug!("Starting server");
         self.view.start();
@@ -104,24 +1005,49 @@ impl Application {
         drop(listener);
     }
 
-    async fn handle_events(&mut self, receiver: &mut mpsc::UnboundedReceiver<Key>) {
+    async fn handle_events(&mut self, receiver: &mut mpsc::UnboundedReceiver<Event>) {
+        // The delays of pending keys count from the last event.
+        let mut last_key = Instant::now();
+        let mut which_key_shown = false;
         while !self.quit {
-            let input = receiver.recv().await;
+            let which_key = if !which_key_shown && self.pending_prefix().is_some() {
+                Some(last_key + self.config.which_key_delay)
+            } else {
+                None
+            };
+            let timeout = if self.waits_for_timeout() {
+                Some(last_key + self.config.key_timeout)
+            } else {
+                None
+            };
+            let deadline = [which_key, timeout].iter().flatten().min().copied();
+            let input = match deadline {
+                Some(deadline) => match time::timeout_at(deadline, receiver.recv()).await {
+                    Ok(input) => input,
+                    Err(_) => {
+                        if Some(deadline) == timeout {
+                            self.handle_key_timeout();
+                        } else {
+                            self.show_which_key();
+                            which_key_shown = true;
+                        }
+                        self.view.render(&self.buffer, &self.command);
+                        continue;
+                    }
+                },
+                None => receiver.recv().await,
+            };
             if let Some(event) = input {
                 self.handle_event(event);
+                last_key = Instant::now();
+                which_key_shown = false;
                 self.view.render(&self.buffer, &self.command);
             }
         }
     }
 
-    fn listen(&mut self, sender: mpsc::UnboundedSender<Key>) -> JoinHandle<()> {
-        let stdin = stdin();
-        spawn(move || {
-            for input in stdin.keys() {
-                if let Ok(event) = input {
-                    sender.send(event).unwrap();
-                }
-            };
-        })
+    fn listen(&mut self, sender: mpsc::UnboundedSender<Event>) -> JoinHandle<()> {
+        let escape_timeout = self.config.escape_timeout;
+        spawn(move || read_events(stdin(), escape_timeout, |event| sender.send(event).is_ok()))
     }
 }
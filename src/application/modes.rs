@@ -2,5 +2,26 @@
 pub enum Modes {
     Insert,
     Normal,
+    /// Like `Normal`, but motions extend the selections instead of moving them.
+    Select,
     Command,
+    Picker,
+    /// Typing the pattern of a `/` or `?` search.
+    Search,
+    /// Deciding whether to make each replacement of a substitution.
+    Confirm,
+}
+
+impl Modes {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Modes::Insert => "INSERT",
+            Modes::Normal => "NORMAL",
+            Modes::Select => "SELECT",
+            Modes::Command => "COMMAND",
+            Modes::Picker => "PICKER",
+            Modes::Search => "SEARCH",
+            Modes::Confirm => "CONFIRM",
+        }
+    }
 }
@@ -0,0 +1,76 @@
+/// An entry of a `Picker`.
+pub struct PickerItem {
+    pub label: String,
+    /// The buffer position the item jumps to.
+    pub position: usize,
+}
+
+/// `Picker` holds the state of a list that is narrowed down by typing and from which one item
+/// is chosen, such as the `:symbols` list.
+pub struct Picker {
+    pub title: String,
+    items: Vec<PickerItem>,
+    query: String,
+    /// The index of the highlighted item among the matching ones.
+    selected: usize,
+}
+
+impl Picker {
+    pub fn new(title: &str, items: Vec<PickerItem>) -> Picker {
+        Picker {
+            title: String::from(title),
+            items,
+            query: String::from(""),
+            selected: 0,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Returns the items that match the query, in their original order. An item matches if its
+    /// label contains the characters of the query in order, ignoring case.
+    pub fn matches(&self) -> Vec<&PickerItem> {
+        let query: Vec<char> = self.query.to_lowercase().chars().collect();
+        self.items
+            .iter()
+            .filter(|item| {
+                let mut query = query.iter().peekable();
+                for c in item.label.to_lowercase().chars() {
+                    if query.peek() == Some(&&c) {
+                        query.next();
+                    }
+                }
+                query.peek().is_none()
+            })
+            .collect()
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.selected = 0;
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+
+    /// Moves the highlight down, or up for negative `delta`, wrapping around at either end.
+    pub fn move_selection(&mut self, delta: isize) {
+        let len = self.matches().len() as isize;
+        if len > 0 {
+            self.selected = (self.selected as isize + delta).rem_euclid(len) as usize;
+        }
+    }
+
+    /// Returns the highlighted item, if any item matches.
+    pub fn current(&self) -> Option<&PickerItem> {
+        self.matches().get(self.selected).copied()
+    }
+}
@@ -0,0 +1,223 @@
+use super::modes::Modes;
+use crate::rope_regex::RopeRegex;
+use std::ops::Range;
+
+/// What the pattern typed in the prompt is used for.
+#[derive(Clone, Copy, PartialEq)]
+pub enum RegexAction {
+    /// Searching the buffer, as `/` and `?` do.
+    Search,
+    /// Selecting the matches inside the selections.
+    Select,
+    /// Splitting the selections on the matches.
+    Split,
+    /// Keeping the selections that contain a match.
+    Keep,
+    /// Removing the selections that contain a match.
+    Remove,
+}
+
+impl RegexAction {
+    /// Returns what the prompt starts with, which for searches depends on their direction.
+    pub fn prompt(&self, forward: bool) -> &'static str {
+        match self {
+            RegexAction::Search if forward => "/",
+            RegexAction::Search => "?",
+            RegexAction::Select => "select: ",
+            RegexAction::Split => "split: ",
+            RegexAction::Keep => "keep: ",
+            RegexAction::Remove => "remove: ",
+        }
+    }
+}
+
+/// `Search` holds the state of a pattern being typed, and the last confirmed search that `n`
+/// and `N` repeat. The selection commands that take a pattern share its prompt and history.
+pub struct Search {
+    pub query: String,
+    pub action: RegexAction,
+    /// Whether the search goes towards the end of the buffer.
+    pub forward: bool,
+    /// The selections before the search started, restored when it is cancelled and searched
+    /// from while it is typed.
+    pub origin: Vec<Range<usize>>,
+    /// The mode the search was started from, returned to when it is done.
+    pub mode: Modes,
+    /// The regex of the last confirmed search.
+    pub last: Option<RopeRegex>,
+    /// The confirmed queries, oldest first.
+    history: Vec<String>,
+    /// The entry of `history` that the query was recalled from.
+    history_index: Option<usize>,
+}
+
+impl Search {
+    pub fn new() -> Search {
+        Search {
+            query: String::from(""),
+            action: RegexAction::Search,
+            forward: true,
+            origin: vec![],
+            mode: Modes::Normal,
+            last: None,
+            history: vec![],
+            history_index: None,
+        }
+    }
+
+    pub fn start(&mut self, action: RegexAction, origin: Vec<Range<usize>>, mode: Modes) {
+        self.query.clear();
+        self.action = action;
+        self.origin = origin;
+        self.mode = mode;
+        self.history_index = None;
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.history_index = None;
+    }
+
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.history_index = None;
+    }
+
+    /// Replaces the query with the previous entry of the history.
+    pub fn history_prev(&mut self) {
+        let index = match self.history_index {
+            Some(index) => index.saturating_sub(1),
+            None if !self.history.is_empty() => self.history.len() - 1,
+            None => return,
+        };
+        self.history_index = Some(index);
+        self.query = self.history[index].clone();
+    }
+
+    /// Replaces the query with the next entry of the history, or clears it past the newest one.
+    pub fn history_next(&mut self) {
+        if let Some(index) = self.history_index {
+            if index + 1 < self.history.len() {
+                self.history_index = Some(index + 1);
+                self.query = self.history[index + 1].clone();
+            } else {
+                self.history_index = None;
+                self.query.clear();
+            }
+        }
+    }
+
+    /// Compiles the query, or the last one in the history when it is empty.
+    pub fn regex(&self) -> Result<RopeRegex, String> {
+        match (self.query.is_empty(), self.history.last()) {
+            (true, Some(last)) => RopeRegex::new(last),
+            _ => RopeRegex::new(&self.query),
+        }
+    }
+
+    /// Compiles the query and moves it to the end of the history.
+    pub fn confirm(&mut self) -> Result<RopeRegex, String> {
+        let regex = self.regex()?;
+        if !self.query.is_empty() {
+            let query = std::mem::take(&mut self.query);
+            self.history.retain(|entry| *entry != query);
+            self.history.push(query);
+        }
+        Ok(regex)
+    }
+}
+
+/// Returns the index of the match to go to from `position`, and whether the search wrapped
+/// around the end of the buffer to find it. Searching forward, that is the first match that
+/// starts after `position`, or at it with `inclusive`. Searching backward, it is the last match
+/// that starts before it.
+pub fn next_match(
+    matches: &[Range<usize>],
+    position: usize,
+    forward: bool,
+    inclusive: bool,
+) -> Option<(usize, bool)> {
+    if matches.is_empty() {
+        return None;
+    }
+    let after = matches
+        .iter()
+        .position(|m| m.start > position || (inclusive && m.start == position));
+    let before = matches.iter().rposition(|m| m.start < position);
+    match (forward, after, before) {
+        (true, Some(index), _) => Some((index, false)),
+        (true, None, _) => Some((0, true)),
+        (false, _, Some(index)) => Some((index, false)),
+        (false, _, None) => Some((matches.len() - 1, true)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a search with `queries` confirmed in order.
+    fn search_with_history(queries: &[&str]) -> Search {
+        let mut search = Search::new();
+        for query in queries {
+            search.start(RegexAction::Search, vec![], Modes::Normal);
+            query.chars().for_each(|c| search.push(c));
+            search.confirm().unwrap();
+        }
+        search.start(RegexAction::Search, vec![], Modes::Normal);
+        search
+    }
+
+    #[test]
+    fn next_match_goes_forward_and_wraps_around() {
+        let matches = [2..4, 8..9, 12..15];
+        assert_eq!(next_match(&matches, 0, true, false), Some((0, false)));
+        assert_eq!(next_match(&matches, 2, true, false), Some((1, false)));
+        assert_eq!(next_match(&matches, 2, true, true), Some((0, false)));
+        assert_eq!(next_match(&matches, 12, true, false), Some((0, true)));
+    }
+
+    #[test]
+    fn next_match_goes_backward_and_wraps_around() {
+        let matches = [2..4, 8..9, 12..15];
+        assert_eq!(next_match(&matches, 12, false, false), Some((1, false)));
+        assert_eq!(next_match(&matches, 20, false, false), Some((2, false)));
+        assert_eq!(next_match(&matches, 2, false, false), Some((2, true)));
+        assert_eq!(next_match(&[], 2, false, false), None);
+    }
+
+    #[test]
+    fn history_is_walked_from_the_newest_entry() {
+        let mut search = search_with_history(&["foo", "bar", "baz"]);
+        search.history_prev();
+        assert_eq!(search.query, "baz");
+        search.history_prev();
+        search.history_prev();
+        search.history_prev();
+        assert_eq!(search.query, "foo");
+        search.history_next();
+        assert_eq!(search.query, "bar");
+        search.history_next();
+        search.history_next();
+        assert_eq!(search.query, "");
+    }
+
+    #[test]
+    fn confirming_a_query_moves_it_to_the_end_of_the_history() {
+        let mut search = search_with_history(&["foo", "bar", "foo"]);
+        search.history_prev();
+        assert_eq!(search.query, "foo");
+        search.history_prev();
+        assert_eq!(search.query, "bar");
+        search.history_prev();
+        assert_eq!(search.query, "bar");
+    }
+
+    #[test]
+    fn empty_query_repeats_the_last_search() {
+        let mut search = search_with_history(&["foo", "b.r"]);
+        assert_eq!(search.confirm().unwrap().as_str(), "b.r");
+        search.push('(');
+        assert!(search.confirm().is_err());
+    }
+}
@@ -0,0 +1,407 @@
+use std::ops::Range;
+
+/// The text a substitution replaces matches in.
+pub enum Target {
+    /// The lines the selections are on, when no range is given.
+    SelectedLines,
+    /// The text of the selections, given as `*`.
+    Selections,
+    /// The whole buffer, given as `%`.
+    All,
+    /// The lines from the first to the last one given, counted from 0.
+    Lines(Range<usize>),
+}
+
+/// A `:s/pattern/replacement/flags` command. The pattern and replacement are separated by the
+/// character following the `s`, which is escaped with a backslash to be used in them.
+pub struct Substitution {
+    pub target: Target,
+    /// The pattern to replace, empty to reuse the last search.
+    pub pattern: String,
+    pub replacement: Replacement,
+    /// Whether every match of a line is replaced, instead of only the first one.
+    pub global: bool,
+    /// Whether case is ignored (`i`) or respected (`I`), instead of depending on the pattern.
+    pub ignore_case: Option<bool>,
+    /// Whether every replacement is confirmed first.
+    pub confirm: bool,
+}
+
+impl Substitution {
+    /// Parses a substitution command, with `current` and `last` being the lines `.` and `$`
+    /// refer to. Returns `None` when `command` is not a substitution.
+    pub fn parse(
+        command: &str,
+        current: usize,
+        last: usize,
+    ) -> Option<Result<Substitution, String>> {
+        let (target, rest) = parse_target(command, current, last)?;
+        let rest = rest.strip_prefix('s')?;
+        let delimiter = rest.chars().next()?;
+        if delimiter.is_alphanumeric() || delimiter.is_whitespace() || delimiter == '\\' {
+            return None;
+        }
+        let target = match target {
+            Ok(target) => target,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut parts = split_unescaped(&rest[delimiter.len_utf8()..], delimiter).into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = Replacement::parse(&parts.next().unwrap_or_default());
+        let mut substitution = Substitution {
+            target,
+            pattern,
+            replacement,
+            global: false,
+            ignore_case: None,
+            confirm: false,
+        };
+        for flag in parts.next().unwrap_or_default().chars() {
+            match flag {
+                'g' => substitution.global = true,
+                'i' => substitution.ignore_case = Some(true),
+                'I' => substitution.ignore_case = Some(false),
+                'c' => substitution.confirm = true,
+                _ => return Some(Err(format!("Unknown substitution flag: {}", flag))),
+            }
+        }
+        Some(Ok(substitution))
+    }
+}
+
+/// Splits the range in front of a command from it. The range is either `%`, `*` or one or two
+/// line addresses separated by a comma: a line number, `.` or `$`.
+fn parse_target(
+    command: &str,
+    current: usize,
+    last: usize,
+) -> Option<(Result<Target, String>, &str)> {
+    if let Some(rest) = command.strip_prefix('%') {
+        return Some((Ok(Target::All), rest));
+    }
+    if let Some(rest) = command.strip_prefix('*') {
+        return Some((Ok(Target::Selections), rest));
+    }
+    let (first, rest) = parse_address(command, current, last);
+    let first = match first {
+        Some(first) => first,
+        None => return Some((Ok(Target::SelectedLines), command)),
+    };
+    let (second, rest) = match rest.strip_prefix(',') {
+        Some(rest) => match parse_address(rest, current, last) {
+            (Some(second), rest) => (second, rest),
+            (None, _) => return None,
+        },
+        None => (first, rest),
+    };
+    let target = if first == 0 || second == 0 {
+        Err(String::from("Lines are counted from 1"))
+    } else if first > second {
+        Err(String::from("Backwards range"))
+    } else {
+        Ok(Target::Lines(first - 1..second.min(last + 1)))
+    };
+    Some((target, rest))
+}
+
+/// Reads a line address at the start of `text`, counted from 1.
+fn parse_address(text: &str, current: usize, last: usize) -> (Option<usize>, &str) {
+    if let Some(rest) = text.strip_prefix('.') {
+        return (Some(current + 1), rest);
+    }
+    if let Some(rest) = text.strip_prefix('$') {
+        return (Some(last + 1), rest);
+    }
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    match text[..digits].parse() {
+        Ok(line) => (Some(line), &text[digits..]),
+        Err(_) => (None, text),
+    }
+}
+
+/// Splits `text` on the occurrences of `delimiter` that are not escaped. Escaped delimiters lose
+/// their backslash, other escapes are kept for the pattern and replacement to interpret.
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::from("")];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => part.push(next),
+                Some(next) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            },
+            _ if c == delimiter => parts.push(String::from("")),
+            _ => part.push(c),
+        }
+    }
+    parts
+}
+
+/// A case conversion applied to the text a replacement produces, as `\u`, `\l`, `\U` and `\L`
+/// ask for.
+#[derive(Clone, Copy)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+enum Piece {
+    Text(String),
+    /// The text of a capture group, 0 being the whole match.
+    Group(usize),
+    /// Converts the case of the next character.
+    NextChar(Case),
+    /// Converts the case of the text up to `\E`.
+    Span(Case),
+    EndSpan,
+}
+
+/// The text matches are replaced with. `&` and `\0` stand for the whole match, `\1` to `\9` for
+/// the capture groups, `\n` and `\t` for a new line and a tab. `\u` and `\l` make the next
+/// character uppercase or lowercase, and `\U` and `\L` the text up to `\E`.
+pub struct Replacement {
+    pieces: Vec<Piece>,
+}
+
+impl Replacement {
+    fn parse(text: &str) -> Replacement {
+        let mut pieces = vec![];
+        let mut literal = String::from("");
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            let piece = match c {
+                '&' => Piece::Group(0),
+                '\\' => match chars.next() {
+                    Some(digit @ '0'..='9') => Piece::Group(digit as usize - '0' as usize),
+                    Some('u') => Piece::NextChar(Case::Upper),
+                    Some('l') => Piece::NextChar(Case::Lower),
+                    Some('U') => Piece::Span(Case::Upper),
+                    Some('L') => Piece::Span(Case::Lower),
+                    Some('E') | Some('e') => Piece::EndSpan,
+                    Some('n') => {
+                        literal.push('\n');
+                        continue;
+                    }
+                    Some('t') => {
+                        literal.push('\t');
+                        continue;
+                    }
+                    Some(escaped) => {
+                        literal.push(escaped);
+                        continue;
+                    }
+                    None => {
+                        literal.push('\\');
+                        continue;
+                    }
+                },
+                _ => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                pieces.push(Piece::Text(std::mem::take(&mut literal)));
+            }
+            pieces.push(piece);
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Text(literal));
+        }
+        Replacement { pieces }
+    }
+
+    /// Builds the replacement of a match from the text of its capture groups. Groups that did
+    /// not take part in the match are left empty.
+    pub fn expand(&self, groups: &[Option<String>]) -> String {
+        let mut result = String::from("");
+        let mut next_char = None;
+        let mut span = None;
+        for piece in &self.pieces {
+            let text = match piece {
+                Piece::Text(text) => text.as_str(),
+                Piece::Group(index) => groups.get(*index).and_then(Option::as_deref).unwrap_or(""),
+                Piece::NextChar(case) => {
+                    next_char = Some(*case);
+                    continue;
+                }
+                Piece::Span(case) => {
+                    span = Some(*case);
+                    continue;
+                }
+                Piece::EndSpan => {
+                    span = None;
+                    continue;
+                }
+            };
+            for c in text.chars() {
+                match next_char.take().or(span) {
+                    Some(Case::Upper) => result.extend(c.to_uppercase()),
+                    Some(Case::Lower) => result.extend(c.to_lowercase()),
+                    None => result.push(c),
+                }
+            }
+        }
+        result
+    }
+}
+
+/// A substitution going through its matches one at a time, as its `c` flag asks for.
+pub struct Confirmation {
+    /// The matches with their replacements, in order, shifted by the replacements made so far.
+    changes: Vec<(Range<usize>, String)>,
+    /// The match being asked about.
+    index: usize,
+    pub replaced: usize,
+}
+
+impl Confirmation {
+    pub fn new(changes: Vec<(Range<usize>, String)>) -> Confirmation {
+        Confirmation {
+            changes,
+            index: 0,
+            replaced: 0,
+        }
+    }
+
+    pub fn current(&self) -> Option<&(Range<usize>, String)> {
+        self.changes.get(self.index)
+    }
+
+    /// Returns the matches from the current one on to replace them all at once.
+    pub fn take_rest(&mut self) -> Vec<(Range<usize>, String)> {
+        let rest = self.changes.split_off(self.index.min(self.changes.len()));
+        self.replaced += rest.len();
+        rest
+    }
+
+    /// Skips the matches that are left.
+    pub fn stop(&mut self) {
+        self.index = self.changes.len();
+    }
+
+    /// Moves on to the next match, after replacing the current one when `replace` is set.
+    pub fn next(&mut self, replace: bool) {
+        if replace {
+            if let Some((range, text)) = self.current() {
+                let delta = text.chars().count() as isize - range.len() as isize;
+                for (range, _) in &mut self.changes[self.index + 1..] {
+                    *range = shift(range.start, delta)..shift(range.end, delta);
+                }
+                self.replaced += 1;
+            }
+        }
+        self.index += 1;
+    }
+}
+
+fn shift(position: usize, delta: isize) -> usize {
+    (position as isize + delta) as usize
+}
+
+/// Describes how many matches there are, such as "1 match" or "3 matches".
+pub fn match_count(count: usize) -> String {
+    if count == 1 {
+        String::from("1 match")
+    } else {
+        format!("{} matches", count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command: &str) -> Substitution {
+        Substitution::parse(command, 4, 9).unwrap().unwrap()
+    }
+
+    fn parse_err(command: &str) -> String {
+        Substitution::parse(command, 4, 9).unwrap().err().unwrap()
+    }
+
+    fn expand(replacement: &str, groups: &[Option<&str>]) -> String {
+        let groups: Vec<Option<String>> = groups.iter().map(|g| g.map(String::from)).collect();
+        Replacement::parse(replacement).expand(&groups)
+    }
+
+    #[test]
+    fn other_commands_are_not_substitutions() {
+        for command in &["w", "q!", "set", "sa", "s b", "%", "s\\a\\b"] {
+            assert!(Substitution::parse(command, 4, 9).is_none(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn pattern_replacement_and_flags_are_split_on_the_delimiter() {
+        let substitution = parse("s/a\\/b/c/gic");
+        assert_eq!(substitution.pattern, "a/b");
+        assert_eq!(substitution.replacement.expand(&[]), "c");
+        assert!(substitution.global);
+        assert_eq!(substitution.ignore_case, Some(true));
+        assert!(substitution.confirm);
+
+        let substitution = parse("s#a/b#c");
+        assert_eq!(substitution.pattern, "a/b");
+        assert!(!substitution.global && !substitution.confirm);
+        assert_eq!(substitution.ignore_case, None);
+        assert_eq!(parse("s/a/b/I").ignore_case, Some(false));
+        assert_eq!(parse("s/a").pattern, "a");
+        assert_eq!(parse_err("s/a/b/x"), "Unknown substitution flag: x");
+    }
+
+    #[test]
+    fn without_a_range_the_selected_lines_are_targeted() {
+        assert!(matches!(parse("s/a/b").target, Target::SelectedLines));
+        assert!(matches!(parse("*s/a/b").target, Target::Selections));
+        assert!(matches!(parse("%s/a/b").target, Target::All));
+    }
+
+    #[test]
+    fn line_ranges_are_counted_from_1() {
+        let lines = |command| match parse(command).target {
+            Target::Lines(lines) => lines,
+            _ => panic!("{} has no line range", command),
+        };
+        assert_eq!(lines("3s/a/b"), 2..3);
+        assert_eq!(lines("2,4s/a/b"), 1..4);
+        assert_eq!(lines(".s/a/b"), 4..5);
+        assert_eq!(lines(".,$s/a/b"), 4..10);
+        assert_eq!(lines("8,20s/a/b"), 7..10);
+        assert_eq!(parse_err("0s/a/b"), "Lines are counted from 1");
+        assert_eq!(parse_err("4,2s/a/b"), "Backwards range");
+        assert!(Substitution::parse("2,s/a/b", 4, 9).is_none());
+    }
+
+    #[test]
+    fn only_escaped_delimiters_lose_their_backslash() {
+        assert_eq!(split_unescaped("a/b\\/c/", '/'), ["a", "b/c", ""]);
+        assert_eq!(split_unescaped("\\d+|x\\", '|'), ["\\d+", "x\\"]);
+        assert_eq!(split_unescaped("", '/'), [""]);
+    }
+
+    #[test]
+    fn groups_are_replaced_with_their_text() {
+        let groups = [Some("key = value"), Some("key"), None];
+        assert_eq!(expand("[&]", &groups), "[key = value]");
+        assert_eq!(expand("\\1: \\0", &groups), "key: key = value");
+        assert_eq!(expand("<\\2\\9>", &groups), "<>");
+        assert_eq!(expand("\\&\\\\a\\n\\t", &groups), "&\\a\n\t");
+        assert_eq!(expand("x\\", &groups), "x\\");
+    }
+
+    #[test]
+    fn case_escapes_convert_the_replacement() {
+        let groups = [Some("hello world"), Some("hello"), Some("WORLD")];
+        assert_eq!(expand("\\u\\1 \\l\\2", &groups), "Hello wORLD");
+        assert_eq!(expand("\\U\\1\\E \\2", &groups), "HELLO WORLD");
+        assert_eq!(expand("\\L\\2 \\1", &groups), "world hello");
+        assert_eq!(expand("\\U\\l&", &groups), "hELLO WORLD");
+        assert_eq!(expand("\\uße", &groups), "SSe");
+    }
+}
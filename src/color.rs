@@ -0,0 +1,351 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// The xterm defaults for the 16 basic ANSI colors, used to find the nearest basic color for an
+/// RGB value.
+const ANSI_16_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Index of the `colors` capability in the numbers section of a compiled terminfo entry.
+const TERMINFO_COLORS: usize = 13;
+
+/// How many colors the terminal is able to display.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSupport {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorSupport {
+    /// Detects the color support of the current terminal from `COLORTERM` and the terminfo entry
+    /// for `TERM`.
+    pub fn detect() -> ColorSupport {
+        if let Ok(colorterm) = env::var("COLORTERM") {
+            if colorterm == "truecolor" || colorterm == "24bit" {
+                return ColorSupport::TrueColor;
+            }
+        }
+
+        let term = env::var("TERM").unwrap_or_default();
+        match terminfo_colors(&term) {
+            Some(colors) if colors >= 1 << 24 => ColorSupport::TrueColor,
+            Some(colors) if colors >= 256 => ColorSupport::Ansi256,
+            Some(_) => ColorSupport::Ansi16,
+            None if term.ends_with("256color") => ColorSupport::Ansi256,
+            None => ColorSupport::Ansi16,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Color {
+    Reset,
+    /// An index into the 256-color palette. The first 16 are the basic ANSI colors.
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Returns the escape sequence that sets this as the foreground color, approximated to what
+    /// the terminal supports.
+    pub fn fg_string(&self, support: ColorSupport) -> String {
+        match self.downsample(support) {
+            Color::Reset => String::from("\x1b[39m"),
+            Color::Ansi(n) if n < 8 => format!("\x1b[{}m", 30 + n),
+            Color::Ansi(n) if n < 16 => format!("\x1b[{}m", 90 + n - 8),
+            Color::Ansi(n) => format!("\x1b[38;5;{}m", n),
+            Color::Rgb(r, g, b) => format!("\x1b[38;2;{};{};{}m", r, g, b),
+        }
+    }
+
+    /// Returns the escape sequence that sets this as the background color, approximated to what
+    /// the terminal supports.
+    pub fn bg_string(&self, support: ColorSupport) -> String {
+        match self.downsample(support) {
+            Color::Reset => String::from("\x1b[49m"),
+            Color::Ansi(n) if n < 8 => format!("\x1b[{}m", 40 + n),
+            Color::Ansi(n) if n < 16 => format!("\x1b[{}m", 100 + n - 8),
+            Color::Ansi(n) => format!("\x1b[48;5;{}m", n),
+            Color::Rgb(r, g, b) => format!("\x1b[48;2;{};{};{}m", r, g, b),
+        }
+    }
+
+    fn downsample(&self, support: ColorSupport) -> Color {
+        match (*self, support) {
+            (Color::Rgb(r, g, b), ColorSupport::Ansi256) => Color::Ansi(nearest_ansi_256(r, g, b)),
+            (Color::Rgb(r, g, b), ColorSupport::Ansi16) => Color::Ansi(nearest_ansi_16(r, g, b)),
+            (Color::Ansi(n), ColorSupport::Ansi16) if n >= 16 => {
+                let (r, g, b) = ansi_to_rgb(n);
+                Color::Ansi(nearest_ansi_16(r, g, b))
+            }
+            (color, _) => color,
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let dr = a.0 as i32 - b.0 as i32;
+    let dg = a.1 as i32 - b.1 as i32;
+    let db = a.2 as i32 - b.2 as i32;
+    (dr * dr + dg * dg + db * db) as u32
+}
+
+fn ansi_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => ANSI_16_PALETTE[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            (
+                CUBE_LEVELS[(n / 36) as usize],
+                CUBE_LEVELS[(n / 6 % 6) as usize],
+                CUBE_LEVELS[(n % 6) as usize],
+            )
+        }
+        _ => {
+            let level = 8 + (n - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+fn nearest_ansi_16(r: u8, g: u8, b: u8) -> u8 {
+    // The basic palette is too coarse for plain distance to work well: muted colors end up
+    // closest to grey. Colors are instead matched against the chromatic or the grey entries
+    // depending on how saturated they are.
+    let saturation = r.max(g).max(b) - r.min(g).min(b);
+    let candidates: &[u8] = if saturation >= 48 {
+        &[1, 2, 3, 4, 5, 6, 9, 10, 11, 12, 13, 14]
+    } else {
+        &[0, 7, 8, 15]
+    };
+    *candidates
+        .iter()
+        .min_by_key(|n| distance(ANSI_16_PALETTE[**n as usize], (r, g, b)))
+        .unwrap()
+}
+
+fn nearest_ansi_256(r: u8, g: u8, b: u8) -> u8 {
+    let cube_index = |v: u8| -> u8 {
+        if v < 48 {
+            0
+        } else if v < 115 {
+            1
+        } else {
+            (v - 35) / 40
+        }
+    };
+    let cube = 16 + 36 * cube_index(r) + 6 * cube_index(g) + cube_index(b);
+
+    let average = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+    let grey = if average < 8 {
+        232
+    } else if average > 238 {
+        255
+    } else {
+        232 + (average - 8) / 10
+    };
+
+    if distance(ansi_to_rgb(grey), (r, g, b)) < distance(ansi_to_rgb(cube), (r, g, b)) {
+        grey
+    } else {
+        cube
+    }
+}
+
+fn terminfo_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![];
+    if let Ok(dir) = env::var("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Ok(home) = env::var("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+    if let Ok(list) = env::var("TERMINFO_DIRS") {
+        dirs.extend(list.split(':').filter(|d| !d.is_empty()).map(PathBuf::from));
+    }
+    for dir in &[
+        "/etc/terminfo",
+        "/lib/terminfo",
+        "/usr/share/terminfo",
+        "/usr/lib/terminfo",
+    ] {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs
+}
+
+/// Reads the `colors` capability from the compiled terminfo entry for `term`.
+fn terminfo_colors(term: &str) -> Option<i32> {
+    let first = term.chars().next()?;
+    let data = terminfo_dirs().iter().find_map(|dir| {
+        fs::read(dir.join(first.to_string()).join(term))
+            .or_else(|_| fs::read(dir.join(format!("{:x}", first as u32)).join(term)))
+            .ok()
+    })?;
+    parse_terminfo_colors(&data)
+}
+
+/// Reads the `colors` capability from a compiled terminfo entry, in either the legacy format with
+/// 16-bit numbers or the extended one with 32-bit numbers.
+fn parse_terminfo_colors(data: &[u8]) -> Option<i32> {
+    let short = |i: usize| -> Option<i32> {
+        let bytes = data.get(i * 2..i * 2 + 2)?;
+        Some(i16::from_le_bytes([bytes[0], bytes[1]]) as i32)
+    };
+    let number_size = match short(0)? {
+        0o432 => 2,
+        0o1036 => 4,
+        _ => return None,
+    };
+    let names_size = short(1)? as usize;
+    let bools_count = short(2)? as usize;
+    let numbers_count = short(3)? as usize;
+    if TERMINFO_COLORS >= numbers_count {
+        return None;
+    }
+
+    let mut offset = 12 + names_size + bools_count;
+    offset += offset % 2;
+    offset += TERMINFO_COLORS * number_size;
+    let bytes = data.get(offset..offset + number_size)?;
+    let colors = if number_size == 2 {
+        i16::from_le_bytes([bytes[0], bytes[1]]) as i32
+    } else {
+        i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    };
+    if colors < 0 {
+        None
+    } else {
+        Some(colors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a compiled terminfo entry with the given numbers, after a name and boolean section of
+    /// the given sizes.
+    fn terminfo(magic: i16, names: &str, bools: usize, numbers: &[i32]) -> Vec<u8> {
+        let mut data = vec![];
+        let names_size = names.len() + 1;
+        for short in &[
+            magic,
+            names_size as i16,
+            bools as i16,
+            numbers.len() as i16,
+            0,
+            0,
+        ] {
+            data.extend_from_slice(&short.to_le_bytes());
+        }
+        data.extend_from_slice(names.as_bytes());
+        data.push(0);
+        data.extend(std::iter::repeat(1).take(bools));
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+        for number in numbers {
+            if magic == 0o432 {
+                data.extend_from_slice(&(*number as i16).to_le_bytes());
+            } else {
+                data.extend_from_slice(&number.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn numbers_with_colors(colors: i32) -> Vec<i32> {
+        let mut numbers = vec![-1; TERMINFO_COLORS + 2];
+        numbers[TERMINFO_COLORS] = colors;
+        numbers
+    }
+
+    #[test]
+    fn colors_are_read_from_the_legacy_format() {
+        let data = terminfo(0o432, "xterm-256color|xterm", 38, &numbers_with_colors(256));
+        assert_eq!(parse_terminfo_colors(&data), Some(256));
+    }
+
+    #[test]
+    fn colors_are_read_from_the_extended_format() {
+        let data = terminfo(0o1036, "xterm-direct", 38, &numbers_with_colors(1 << 24));
+        assert_eq!(parse_terminfo_colors(&data), Some(1 << 24));
+    }
+
+    #[test]
+    fn numbers_are_aligned_to_an_even_offset() {
+        // 12 header bytes and a 4 byte name leave the numbers unaligned after an odd boolean count.
+        for bools in 0..4 {
+            let data = terminfo(0o432, "xyz", bools, &numbers_with_colors(8));
+            assert_eq!(parse_terminfo_colors(&data), Some(8), "{} booleans", bools);
+        }
+    }
+
+    #[test]
+    fn missing_or_invalid_colors_are_ignored() {
+        let data = terminfo(0o432, "dumb", 2, &numbers_with_colors(-1));
+        assert_eq!(parse_terminfo_colors(&data), None);
+        let data = terminfo(0o432, "dumb", 2, &[80, -1, 24]);
+        assert_eq!(parse_terminfo_colors(&data), None);
+        let data = terminfo(0o433, "xterm", 2, &numbers_with_colors(8));
+        assert_eq!(parse_terminfo_colors(&data), None);
+        let mut data = terminfo(0o432, "xterm", 2, &numbers_with_colors(8));
+        data.truncate(data.len() - 4);
+        assert_eq!(parse_terminfo_colors(&data), None);
+    }
+
+    #[test]
+    fn rgb_is_matched_to_the_nearest_256_palette_entry() {
+        assert_eq!(nearest_ansi_256(0, 0, 0), 16);
+        assert_eq!(nearest_ansi_256(255, 255, 255), 231);
+        assert_eq!(nearest_ansi_256(255, 0, 0), 196);
+        assert_eq!(nearest_ansi_256(0, 135, 255), 33);
+        assert_eq!(nearest_ansi_256(128, 128, 128), 244);
+        assert_eq!(nearest_ansi_256(30, 30, 30), 234);
+    }
+
+    #[test]
+    fn rgb_is_matched_to_the_nearest_basic_color() {
+        assert_eq!(nearest_ansi_16(0, 0, 0), 0);
+        assert_eq!(nearest_ansi_16(250, 250, 250), 15);
+        assert_eq!(nearest_ansi_16(128, 128, 128), 8);
+        assert_eq!(nearest_ansi_16(200, 10, 10), 1);
+        assert_eq!(nearest_ansi_16(250, 60, 50), 9);
+        assert_eq!(nearest_ansi_16(0, 0, 200), 4);
+    }
+
+    #[test]
+    fn colors_are_downsampled_to_the_terminal_support() {
+        let orange = Color::Rgb(255, 135, 0);
+        assert_eq!(
+            orange.fg_string(ColorSupport::TrueColor),
+            "\x1b[38;2;255;135;0m"
+        );
+        assert_eq!(orange.fg_string(ColorSupport::Ansi256), "\x1b[38;5;208m");
+        assert_eq!(orange.bg_string(ColorSupport::Ansi16), "\x1b[43m");
+        assert_eq!(Color::Ansi(196).fg_string(ColorSupport::Ansi16), "\x1b[91m");
+        assert_eq!(Color::Ansi(3).bg_string(ColorSupport::Ansi16), "\x1b[43m");
+        assert_eq!(Color::Reset.fg_string(ColorSupport::Ansi16), "\x1b[39m");
+    }
+}
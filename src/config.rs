@@ -0,0 +1,266 @@
+use crate::language::{language_by_name, Indent, LanguageConfig};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use toml::Value;
+
+/// Returns the directory user configuration is read from: `$XDG_CONFIG_HOME/some-editor`, or
+/// `~/.config/some-editor` when `XDG_CONFIG_HOME` is not set.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(base.join("some-editor"))
+}
+
+/// Returns the directories runtime files such as themes are looked up in, in order of priority.
+/// Files found there override the ones built into the editor.
+pub fn runtime_dirs() -> Vec<PathBuf> {
+    config_dir().into_iter().collect()
+}
+
+/// Settings read from `config.toml` in the config directory:
+///
+/// ```toml
+/// [editor]
+/// theme = "gruvbox"
+/// sticky-context = 3
+/// rainbow-brackets = false
+/// find-across-lines = false
+/// key-timeout = 1000
+/// which-key-delay = 500
+/// escape-timeout = 30
+///
+/// [languages.python]
+/// indent-width = 4
+/// indent-tabs = false
+///
+/// [keys.normal]
+/// "<space>fs" = ":symbols"
+/// "x" = "no_op"
+/// ```
+pub struct Config {
+    /// The theme loaded at startup, instead of the default one.
+    pub theme: Option<String>,
+    /// The maximum number of enclosing scopes pinned to the top of the view. `0` turns the sticky
+    /// context off.
+    pub sticky_context: usize,
+    /// Whether brackets are colored by how deeply they are nested, using the theme's `rainbow`
+    /// colors.
+    pub rainbow_brackets: bool,
+    /// Whether `f`, `t`, `F` and `T` search past the end of the line.
+    pub find_across_lines: bool,
+    /// How long to wait for more keys after a sequence that is both bound and the start of longer
+    /// ones.
+    pub key_timeout: Duration,
+    /// How long keys that start longer sequences are pending before the keys that can follow
+    /// them are listed.
+    pub which_key_delay: Duration,
+    /// How long to wait for the rest of an escape sequence before taking an escape as the Esc
+    /// key.
+    pub escape_timeout: Duration,
+    /// The indentation of the languages whose defaults are overridden, by language name.
+    pub indents: HashMap<String, Indent>,
+    /// The key bindings overriding the default ones, as `(keys, command)` pairs by mode name.
+    pub keys: HashMap<String, Vec<(String, String)>>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            theme: None,
+            sticky_context: 3,
+            rainbow_brackets: false,
+            find_across_lines: false,
+            key_timeout: Duration::from_millis(1000),
+            which_key_delay: Duration::from_millis(500),
+            escape_timeout: Duration::from_millis(30),
+            indents: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "could not read config: {}", err),
+            ConfigError::Parse(err) => write!(f, "could not parse config: {}", err),
+            ConfigError::Invalid(reason) => write!(f, "invalid config: {}", reason),
+        }
+    }
+}
+
+impl Config {
+    /// Loads `config.toml` from the config directory. A missing file gives the default config.
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = match config_dir() {
+            Some(dir) => dir.join("config.toml"),
+            None => return Ok(Config::default()),
+        };
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Config::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Config, ConfigError> {
+        let value: Value = toml::from_str(source).map_err(ConfigError::Parse)?;
+        let mut config = Config::default();
+        if let Some(editor) = value.get("editor") {
+            if let Some(theme) = editor.get("theme") {
+                let name = theme.as_str().ok_or_else(|| {
+                    ConfigError::Invalid(String::from("theme must be the name of a theme"))
+                })?;
+                config.theme = Some(name.to_string());
+            }
+            if let Some(depth) = editor.get("sticky-context") {
+                config.sticky_context =
+                    depth
+                        .as_integer()
+                        .filter(|depth| *depth >= 0)
+                        .ok_or_else(|| {
+                            ConfigError::Invalid(String::from(
+                                "sticky-context must be a number of lines",
+                            ))
+                        })? as usize;
+            }
+            if let Some(rainbow) = editor.get("rainbow-brackets") {
+                config.rainbow_brackets = rainbow.as_bool().ok_or_else(|| {
+                    ConfigError::Invalid(String::from("rainbow-brackets must be true or false"))
+                })?;
+            }
+            if let Some(across_lines) = editor.get("find-across-lines") {
+                config.find_across_lines = across_lines.as_bool().ok_or_else(|| {
+                    ConfigError::Invalid(String::from("find-across-lines must be true or false"))
+                })?;
+            }
+            config.key_timeout = parse_millis(editor, "key-timeout", config.key_timeout)
+                .map_err(ConfigError::Invalid)?;
+            config.which_key_delay =
+                parse_millis(editor, "which-key-delay", config.which_key_delay)
+                    .map_err(ConfigError::Invalid)?;
+            config.escape_timeout = parse_millis(editor, "escape-timeout", config.escape_timeout)
+                .map_err(ConfigError::Invalid)?;
+        }
+        if let Some(languages) = value.get("languages").and_then(Value::as_table) {
+            for (name, settings) in languages {
+                let language = language_by_name(name)
+                    .ok_or_else(|| ConfigError::Invalid(format!("unknown language: {}", name)))?;
+                let mut indent = language.indent;
+                if let Some(width) = settings.get("indent-width") {
+                    indent.width =
+                        width
+                            .as_integer()
+                            .filter(|width| *width > 0)
+                            .ok_or_else(|| {
+                                ConfigError::Invalid(String::from(
+                                    "indent-width must be a positive number of columns",
+                                ))
+                            })? as usize;
+                }
+                if let Some(tabs) = settings.get("indent-tabs") {
+                    indent.tabs = tabs.as_bool().ok_or_else(|| {
+                        ConfigError::Invalid(String::from("indent-tabs must be true or false"))
+                    })?;
+                }
+                config.indents.insert(language.name.to_string(), indent);
+            }
+        }
+        if let Some(modes) = value.get("keys").and_then(Value::as_table) {
+            for (mode, bindings) in modes {
+                let bindings = bindings.as_table().ok_or_else(|| {
+                    ConfigError::Invalid(format!("keys.{} must be a table", mode))
+                })?;
+                let bindings = bindings
+                    .iter()
+                    .map(|(keys, command)| match command.as_str() {
+                        Some(command) => Ok((keys.clone(), command.to_string())),
+                        None => Err(ConfigError::Invalid(format!(
+                            "the command bound to {} must be a string",
+                            keys
+                        ))),
+                    })
+                    .collect::<Result<_, _>>()?;
+                config.keys.insert(mode.clone(), bindings);
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns how lines of `language` are indented, taking the overrides into account.
+    pub fn indent(&self, language: Option<&LanguageConfig>) -> Indent {
+        match language {
+            Some(language) => self
+                .indents
+                .get(language.name)
+                .copied()
+                .unwrap_or(language.indent),
+            None => Indent::default(),
+        }
+    }
+}
+
+/// Reads the duration set for `key` in `table` as a number of milliseconds, or returns `default`
+/// if it is not set.
+fn parse_millis(table: &Value, key: &str, default: Duration) -> Result<Duration, String> {
+    match table.get(key) {
+        Some(millis) => millis
+            .as_integer()
+            .filter(|millis| *millis >= 0)
+            .map(|millis| Duration::from_millis(millis as u64))
+            .ok_or_else(|| format!("{} must be a number of milliseconds", key)),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        Config::parse(source).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn the_theme_is_picked_by_name() {
+        assert_eq!(Config::parse("").unwrap().theme, None);
+        let config = Config::parse("[editor]\ntheme = \"gruvbox\"").unwrap();
+        assert_eq!(config.theme.as_deref(), Some("gruvbox"));
+        assert_eq!(
+            error("[editor]\ntheme = 1"),
+            "invalid config: theme must be the name of a theme"
+        );
+    }
+
+    #[test]
+    fn durations_are_read_as_milliseconds() {
+        let config = Config::parse("[editor]\nkey-timeout = 250\nescape-timeout = 0").unwrap();
+        assert_eq!(config.key_timeout, Duration::from_millis(250));
+        assert_eq!(config.escape_timeout, Duration::ZERO);
+        assert_eq!(config.which_key_delay, Config::default().which_key_delay);
+    }
+
+    #[test]
+    fn durations_must_be_positive_integers() {
+        for value in &["-1", "1.5", "\"1s\""] {
+            assert_eq!(
+                error(&format!("[editor]\nwhich-key-delay = {}", value)),
+                "invalid config: which-key-delay must be a number of milliseconds"
+            );
+        }
+    }
+}
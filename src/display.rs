@@ -1,3 +1,4 @@
+#[derive(Copy, Clone)]
 pub struct Point {
     pub row: usize,
     pub col: usize,
@@ -12,6 +13,7 @@ struct Cell {
     char: char,
     fg: String,
     bg: String,
+    attrs: String,
 }
 
 impl Cell {
@@ -21,20 +23,26 @@ impl Cell {
             char: char,
             fg: default.fg_str().to_string(),
             bg: default.bg_str().to_string(),
+            attrs: String::from(""),
         }
     }
 }
 
+/// `Display` is a grid of styled cells that is rendered to the terminal in one write. The
+/// leftmost `gutter_width` columns are reserved for the gutter and buffer content is drawn to the
+/// right of them.
 pub struct Display {
     content: Vec<Cell>,
     size: Rect,
+    gutter_width: usize,
 }
 
 impl Display {
-    pub fn new(size: Rect, content: &String) -> Display {
+    pub fn new(size: Rect, gutter_width: usize, content: &String) -> Display {
         let mut display = Display {
             content: vec![],
             size: size,
+            gutter_width,
         };
         display.set_content(content);
         display
@@ -44,8 +52,20 @@ impl Display {
         let mut rendered = String::from("");
         let mut prev_bg = termion::color::Reset.bg_str().to_string();
         let mut prev_fg = termion::color::Reset.fg_str().to_string();
-        for line in self.content.chunks(self.size.width as usize) {
+        let mut prev_attrs = String::from("");
+        for (row, line) in self.content.chunks(self.size.width as usize).enumerate() {
+            rendered.push_str(&termion::cursor::Goto(1, row as u16 + 1).to_string());
             for cell in line.iter() {
+                if prev_attrs != cell.attrs {
+                    // Attributes can only be turned off by a full reset, which also resets colors.
+                    rendered.push_str(termion::style::Reset.as_ref());
+                    rendered.push_str(cell.attrs.as_str());
+                    rendered.push_str(cell.bg.as_str());
+                    rendered.push_str(cell.fg.as_str());
+                    prev_attrs = cell.attrs.clone();
+                    prev_bg = cell.bg.clone();
+                    prev_fg = cell.fg.clone();
+                }
                 if prev_bg != cell.bg {
                     rendered.push_str(cell.bg.as_str());
                     prev_bg = cell.bg.clone();
@@ -54,29 +74,35 @@ impl Display {
                     rendered.push_str(cell.fg.as_str());
                     prev_fg = cell.fg.clone();
                 }
-                if cell.char != '\n' {
+                if cell.char == '\n' {
+                    rendered.push(' ');
+                } else {
                     rendered.push(cell.char);
                 }
             }
-            rendered.push_str(" \r\n");
         }
+        rendered.push_str(termion::style::Reset.as_ref());
         rendered
     }
 
+    /// Fills the content area with `content`, one line per row. Lines wider than the content area
+    /// are truncated.
     pub fn set_content(&mut self, content: &String) {
         self.empty();
 
+        let width = self.size.width as usize;
         let mut row = 0;
-        let mut col = 0;
+        let mut col = self.gutter_width;
         for char in content[..].chars() {
-            let pos = row * self.size.width + col;
-            if pos as usize > self.content.len() - 1 {
+            if row >= self.size.height as usize {
                 return;
             }
-            self.content[pos as usize] = Cell::new(char);
-            if char == '\n' || col == self.size.width {
+            if col < width {
+                self.content[row * width + col] = Cell::new(char);
+            }
+            if char == '\n' {
                 row += 1;
-                col = 0;
+                col = self.gutter_width;
             } else {
                 col += 1;
             }
@@ -85,7 +111,32 @@ impl Display {
 
     fn empty(&mut self) {
         self.content = vec![];
-        (1..self.size.width * self.size.height).for_each(|_| self.content.push(Cell::new(' ')));
+        (0..self.size.width as usize * self.size.height as usize)
+            .for_each(|_| self.content.push(Cell::new(' ')));
+    }
+
+    /// Returns the indexes of the cells between two points of the content area.
+    fn cells_between(&self, start: Point, end: Point) -> Vec<usize> {
+        let width = self.size.width as usize;
+        let mut cells = vec![];
+        for row in start.row..=end.row {
+            if row >= self.size.height as usize {
+                break;
+            }
+            let first = if row == start.row { start.col } else { 0 };
+            let last = if row == end.row {
+                end.col
+            } else {
+                width - self.gutter_width
+            };
+            for col in first..last {
+                let col = col + self.gutter_width;
+                if col < width {
+                    cells.push(row * width + col);
+                }
+            }
+        }
+        cells
     }
 
     pub fn highlight(
@@ -95,18 +146,48 @@ impl Display {
         fg: Option<&String>,
         bg: Option<&String>,
     ) {
-        let start_idx = start.row * self.size.width as usize + start.col;
-        let end_idx = end.row * self.size.width as usize + end.col;
+        for i in self.cells_between(start, end) {
+            if let Some(color) = fg {
+                self.content[i].fg = color.clone();
+            }
+            if let Some(color) = bg {
+                self.content[i].bg = color.clone();
+            }
+        }
+    }
+
+    /// Sets text attributes such as bold or underline between two points of the content area.
+    pub fn decorate(&mut self, start: Point, end: Point, attrs: &str) {
+        for i in self.cells_between(start, end) {
+            self.content[i].attrs = attrs.to_string();
+        }
+    }
 
-        for i in start_idx..end_idx {
-            if i >= self.content.len() {
+    /// Writes `text` at an absolute position, ignoring the gutter. Text that does not fit on the
+    /// row is truncated.
+    pub fn put_str(
+        &mut self,
+        row: usize,
+        col: usize,
+        text: &str,
+        fg: Option<&String>,
+        bg: Option<&String>,
+    ) {
+        let width = self.size.width as usize;
+        if row >= self.size.height as usize {
+            return;
+        }
+        for (i, char) in text.chars().enumerate() {
+            if col + i >= width {
                 return;
             }
+            let cell = &mut self.content[row * width + col + i];
+            cell.char = char;
             if let Some(color) = fg {
-                self.content[i].fg = color.clone();
+                cell.fg = color.clone();
             }
             if let Some(color) = bg {
-                self.content[i].bg = color.clone();
+                cell.bg = color.clone();
             }
         }
     }
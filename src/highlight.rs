@@ -1,152 +1,249 @@
-use termion::color;
-use termion::color::Fg;
-use tree_sitter::Language;
+use crate::application::buffer::Buffer;
+use crate::language::{language_by_name, languages, DocComments, LanguageConfig};
+use crate::theme::{Style, Theme};
+use std::ops::Range;
+use tree_sitter::Node;
 use tree_sitter_highlight::HighlightConfiguration;
 use tree_sitter_highlight::HighlightEvent;
 use tree_sitter_highlight::Highlighter as TSHighlighter;
-extern "C" {
-    fn tree_sitter_rust() -> Language;
+
+fn get_highlight_style(name: &str, theme: &Theme) -> Style {
+    theme.scope(name).cloned().unwrap_or_default()
 }
 
-struct HighlightColor {
-    name: String,
-    color: String,
+/// Returns the position of a language in the registry, which is also the position of its
+/// highlight configuration.
+fn config_index(language: &LanguageConfig) -> Option<usize> {
+    languages()
+        .iter()
+        .position(|config| config.name == language.name)
 }
 
-impl HighlightColor {
-    pub fn new<T: color::Color>(name: &str, color: T) -> HighlightColor {
-        HighlightColor {
-            name: String::from(name),
-            color: Fg(color).to_string(),
-        }
-    }
+/// A styled range of characters.
+pub struct Highlight {
+    pub start: usize,
+    pub end: usize,
+    pub style: Style,
 }
 
-fn get_highlight_config() -> Vec<(usize, HighlightColor)> {
-    vec![
-        (0, HighlightColor::new("attribute", color::Red)),
-        (1, HighlightColor::new("constant", color::Red)),
-        (2, HighlightColor::new("function.builtin", color::Red)),
-        (3, HighlightColor::new("keyword", color::Blue)),
-        (4, HighlightColor::new("function", color::Red)),
-        (5, HighlightColor::new("operator", color::Blue)),
-        (6, HighlightColor::new("property", color::Magenta)),
-        (7, HighlightColor::new("punctuation", color::LightGreen)),
-        (
-            8,
-            HighlightColor::new("punctuation.bracket", color::LightCyan),
-        ),
-        (
-            9,
-            HighlightColor::new("punctuation.delimiter", color::Yellow),
-        ),
-        (10, HighlightColor::new("string", color::Green)),
-        (11, HighlightColor::new("string.special", color::LightGreen)),
-        (12, HighlightColor::new("tag", color::Red)),
-        (13, HighlightColor::new("type", color::Red)),
-        (14, HighlightColor::new("type.builtin", color::Blue)),
-        (15, HighlightColor::new("variable", color::Red)),
-        (
-            16,
-            HighlightColor::new("variable.builtin", color::LightYellow),
-        ),
-        (
-            17,
-            HighlightColor::new("variable.parameter", color::LightMagenta),
-        ),
-        (18, HighlightColor::new("comment", color::LightBlack)),
-        (19, HighlightColor::new("function.method", color::Yellow)),
-        (20, HighlightColor::new("function.special", color::Red)),
-    ]
+/// A piece of a document that is embedded in a buffer, such as the text of one line of a doc
+/// comment.
+struct Segment {
+    /// The byte offset of the segment in the embedded document.
+    start: usize,
+    /// The byte offset of the segment in the buffer.
+    buffer_start: usize,
+    len: usize,
 }
 
-fn get_highlight_names() -> Vec<String> {
-    get_highlight_config()
-        .iter()
-        .map(|name| -> String { name.1.name.clone() })
-        .collect()
+pub struct Highlighter {
+    highlighter: TSHighlighter,
+    /// The highlight configuration of each language, in the order of the language registry.
+    configs: Vec<HighlightConfiguration>,
+    highlight_names: Vec<String>,
 }
 
-fn get_highlight_color(color: usize) -> String {
-    for name in get_highlight_config().iter() {
-        if name.0 == color {
-            return name.1.color.clone();
+/// Highlights `source` with `config`, highlighting injected languages with the configuration
+/// of the language they name. Returns styled byte ranges of `source`.
+fn highlight_source(
+    highlighter: &mut TSHighlighter,
+    configs: &[HighlightConfiguration],
+    config: &HighlightConfiguration,
+    source: &[u8],
+    highlight_names: &[String],
+    theme: &Theme,
+) -> Vec<(Range<usize>, Style)> {
+    let injection_config = |name: &str| {
+        language_by_name(name)
+            .and_then(config_index)
+            .and_then(|index| configs.get(index))
+    };
+    let highlights = match highlighter.highlight(config, source, None, injection_config) {
+        Ok(highlights) => highlights,
+        Err(_) => return vec![],
+    };
+
+    let mut ranges = vec![];
+    let mut styles: Vec<Style> = vec![];
+    for event in highlights {
+        match event {
+            Ok(HighlightEvent::Source { start, end }) => {
+                if let Some(style) = styles.last() {
+                    ranges.push((start..end, style.clone()));
+                }
+            }
+            Ok(HighlightEvent::HighlightStart(s)) => {
+                styles.push(get_highlight_style(&highlight_names[s.0], theme));
+            }
+            Ok(HighlightEvent::HighlightEnd) => {
+                styles.pop();
+            }
+            Err(_) => break,
         }
     }
-    return Fg(color::White).to_string();
+    ranges
 }
 
-pub struct Highlight {
-    pub start: usize,
-    pub end: usize,
-    pub color: String,
-}
+/// Collects the runs of consecutive doc comment lines under `node` as embedded documents, with
+/// the comment prefix stripped from every line.
+fn doc_comment_documents(
+    node: Node,
+    source: &[u8],
+    doc_comments: &DocComments,
+    documents: &mut Vec<(String, Vec<Segment>)>,
+) {
+    let mut cursor = node.walk();
+    let mut last_row = None;
+    for child in node.children(&mut cursor) {
+        if child.kind() != doc_comments.node_kind {
+            if child.child_count() > 0 {
+                doc_comment_documents(child, source, doc_comments, documents);
+            }
+            last_row = None;
+            continue;
+        }
+        let text = &source[child.start_byte()..child.end_byte()];
+        let prefix = match doc_comments
+            .prefixes
+            .iter()
+            .find(|prefix| text.starts_with(prefix.as_bytes()))
+        {
+            Some(prefix) => prefix,
+            None => {
+                last_row = None;
+                continue;
+            }
+        };
 
-pub struct Highlighter {
-    highlighter: TSHighlighter,
-    rust_config: HighlightConfiguration,
+        if last_row.is_none_or(|row| row + 1 != child.start_position().row) {
+            documents.push((String::from(""), vec![]));
+        }
+        last_row = Some(child.end_position().row);
+
+        let (document, segments) = documents.last_mut().unwrap();
+        let content = String::from_utf8_lossy(&text[prefix.len()..]);
+        let content = content.trim_end_matches('\n');
+        segments.push(Segment {
+            start: document.len(),
+            buffer_start: child.start_byte() + prefix.len(),
+            len: content.len(),
+        });
+        document.push_str(content);
+        document.push('\n');
+    }
 }
 
 impl Highlighter {
     pub fn new() -> Highlighter {
-        let rust_language = unsafe { tree_sitter_rust() };
-        let mut rust_config =
-            HighlightConfiguration::new(rust_language, tree_sitter_rust::HIGHLIGHT_QUERY, "", "")
-                .unwrap();
-        rust_config.configure(get_highlight_names().as_slice());
+        let mut configs: Vec<HighlightConfiguration> = languages()
+            .iter()
+            .map(|language| {
+                HighlightConfiguration::new(
+                    language.language,
+                    language.highlights_query,
+                    language.injections_query,
+                    language.locals_query,
+                )
+                .unwrap()
+            })
+            .collect();
+
+        // Injected languages are highlighted in the same pass as the buffer, so every
+        // configuration shares one list of names. Every capture of every query is registered so
+        // that the theme, rather than the highlighter, decides which scopes fall back to their
+        // parents.
+        let mut highlight_names: Vec<String> = configs
+            .iter()
+            .flat_map(|config| config.query.capture_names().to_vec())
+            .collect();
+        highlight_names.sort();
+        highlight_names.dedup();
+        for config in configs.iter_mut() {
+            config.configure(&highlight_names);
+        }
+
         Highlighter {
             highlighter: TSHighlighter::new(),
-            rust_config: rust_config,
+            configs,
+            highlight_names,
         }
     }
 
+    /// Returns the highlights that fall between the `start_bound` and `end_bound` character
+    /// indexes, clipped to those bounds.
     pub fn get_highlights(
         &mut self,
-        text: &String,
+        buffer: &Buffer,
         start_bound: usize,
         end_bound: usize,
+        theme: &Theme,
     ) -> Vec<Highlight> {
-        let highlights = self
-            .highlighter
-            .highlight(&self.rust_config, text.as_bytes(), None, |_| None)
-            .unwrap();
-
-        let mut hls = vec![];
-        let mut hl = Highlight {
-            start: 0,
-            end: 0,
-            color: String::from(""),
+        let language = match buffer.language {
+            Some(language) => language,
+            None => return vec![],
         };
+        let text = &buffer.data;
+        let source = text.to_string();
 
-        for event in highlights {
-            match event.unwrap() {
-                HighlightEvent::Source { start, end } => {
-                    if hl.color.len() == 0 && start >= start_bound && end <= end_bound {
-                        hls.push(Highlight {
-                            start: start,
-                            end: end,
-                            color: termion::color::Reset.fg_str().to_string(),
-                        });
-                    } else {
-                        hl.start = start;
-                        hl.end = end;
-                    }
-                }
-                HighlightEvent::HighlightStart(s) => {
-                    hl.color = get_highlight_color(s.0);
-                }
-                HighlightEvent::HighlightEnd => {
-                    if hl.start >= start_bound && hl.end <= end_bound {
-                        hls.push(hl);
+        let mut ranges = highlight_source(
+            &mut self.highlighter,
+            &self.configs,
+            &self.configs[config_index(language).unwrap()],
+            source.as_bytes(),
+            &self.highlight_names,
+            theme,
+        );
+
+        // tree-sitter can only inject whole nodes, so documents that are spread over several
+        // comments are highlighted separately and mapped back onto the buffer.
+        let doc_config = language
+            .doc_comments
+            .as_ref()
+            .and_then(|doc_comments| language_by_name(doc_comments.language))
+            .and_then(config_index);
+        if let (Some(doc_comments), Some(doc_index), Some(syntax)) =
+            (&language.doc_comments, doc_config, &buffer.syntax)
+        {
+            let mut documents = vec![];
+            doc_comment_documents(
+                syntax.tree().root_node(),
+                source.as_bytes(),
+                doc_comments,
+                &mut documents,
+            );
+            for (document, segments) in documents {
+                let doc_ranges = highlight_source(
+                    &mut self.highlighter,
+                    &self.configs,
+                    &self.configs[doc_index],
+                    document.as_bytes(),
+                    &self.highlight_names,
+                    theme,
+                );
+                for (range, style) in doc_ranges {
+                    for segment in &segments {
+                        let start = range.start.max(segment.start);
+                        let end = range.end.min(segment.start + segment.len);
+                        if start < end {
+                            let offset = segment.buffer_start - segment.start;
+                            ranges.push((start + offset..end + offset, style.clone()));
+                        }
                     }
-                    hl = Highlight {
-                        start: 0,
-                        end: 0,
-                        color: String::from(""),
-                    };
                 }
             }
         }
-        hls
+
+        ranges
+            .into_iter()
+            .filter_map(|(range, style)| {
+                let start = text.byte_to_char(range.start).max(start_bound);
+                let end = text.byte_to_char(range.end).min(end_bound);
+                if start < end {
+                    Some(Highlight { start, end, style })
+                } else {
+                    None
+                }
+            })
+            .collect()
     }
 }
@@ -0,0 +1,544 @@
+use std::io::Read;
+use std::str;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread::spawn;
+use std::time::Duration;
+use termion::event::MouseEvent;
+
+/// The flags pushed to enable the kitty keyboard protocol: escape codes for keys that are
+/// ambiguous otherwise (1), reporting the shifted key along with modifiers (4).
+pub const KEYBOARD_PROTOCOL_FLAGS: u8 = 0b101;
+
+/// The modifier keys held while a key is pressed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub super_key: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        ctrl: false,
+        alt: false,
+        shift: false,
+        super_key: false,
+    };
+
+    /// Reads the modifiers of a CSI sequence, which are encoded as one more than their bits.
+    fn from_param(param: u32) -> Modifiers {
+        let bits = param.saturating_sub(1);
+        Modifiers {
+            shift: bits & 1 != 0,
+            alt: bits & 2 != 0,
+            ctrl: bits & 4 != 0,
+            super_key: bits & 8 != 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KeyCode {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Esc,
+    Delete,
+    Insert,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    F(u8),
+}
+
+/// A key pressed with modifiers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode) -> Key {
+        Key::with_modifiers(code, Modifiers::NONE)
+    }
+
+    pub fn ctrl(c: char) -> Key {
+        Key::with_modifiers(
+            KeyCode::Char(c),
+            Modifiers {
+                ctrl: true,
+                ..Modifiers::NONE
+            },
+        )
+    }
+
+    /// Makes a key, folding shift into letters so that shift-a is the same key as `A` however the
+    /// terminal reports it.
+    pub fn with_modifiers(code: KeyCode, mut modifiers: Modifiers) -> Key {
+        let mut code = code;
+        if let KeyCode::Char(c) = code {
+            if modifiers.shift && c.is_alphabetic() {
+                let mut upper = c.to_uppercase();
+                if let (Some(upper), None) = (upper.next(), upper.next()) {
+                    code = KeyCode::Char(upper);
+                    modifiers.shift = false;
+                }
+            }
+        }
+        Key { code, modifiers }
+    }
+
+    /// Returns the character typed with the key, unless modifiers other than shift are held.
+    pub fn char(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c)
+                if !self.modifiers.ctrl && !self.modifiers.alt && !self.modifiers.super_key =>
+            {
+                Some(c)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Key(Key),
+    Mouse(MouseEvent),
+    /// The terminal answered the query for its keyboard protocol flags, so it supports the kitty
+    /// keyboard protocol.
+    KeyboardProtocol,
+}
+
+/// What parsing the start of the input gave.
+enum Parsed {
+    /// A sequence of this many bytes, which may not stand for any event.
+    Complete(Option<Event>, usize),
+    /// The input ends in the middle of a sequence.
+    Incomplete,
+}
+
+/// Turns the bytes read from the terminal into events. Both the legacy encoding of keys and the
+/// CSI u sequences of the kitty keyboard protocol are understood.
+#[derive(Default)]
+pub struct Parser {
+    /// The bytes of a sequence that is not complete yet.
+    pending: Vec<u8>,
+}
+
+impl Parser {
+    /// Parses `bytes` after the ones left over from before. A sequence cut off at the end is kept
+    /// until more bytes come or `flush` is called.
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Event> {
+        self.pending.extend_from_slice(bytes);
+        let mut events = vec![];
+        let mut start = 0;
+        while start < self.pending.len() {
+            match parse(&self.pending[start..]) {
+                Parsed::Complete(event, len) => {
+                    events.extend(event);
+                    start += len;
+                }
+                Parsed::Incomplete => break,
+            }
+        }
+        self.pending.drain(..start);
+        events
+    }
+
+    /// Whether the bytes of an unfinished sequence are kept.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Parses the kept bytes as if no more are coming: an escape that starts them is the Esc
+    /// key, and the bytes after it are parsed again on their own.
+    pub fn flush(&mut self) -> Vec<Event> {
+        let pending = std::mem::take(&mut self.pending);
+        match pending.split_first() {
+            Some((0x1b, rest)) => {
+                let mut events = vec![Event::Key(Key::new(KeyCode::Esc))];
+                events.extend(self.feed(rest));
+                events.extend(self.flush());
+                events
+            }
+            // What remains is a cut off UTF-8 character, which is dropped.
+            _ => vec![],
+        }
+    }
+}
+
+/// Reads the events typed on `input` and hands them to `send` until it returns `false` or the
+/// input ends. An escape is the Esc key when nothing follows it within `escape_timeout`, and
+/// the start of a sequence or an Alt key otherwise.
+pub fn read_events<R, F>(mut input: R, escape_timeout: Duration, send: F)
+where
+    R: Read + Send + 'static,
+    F: FnMut(Event) -> bool,
+{
+    // Bytes are read on a thread of their own so that waiting for them can time out.
+    let (sender, chunks) = channel();
+    spawn(move || {
+        let mut bytes = [0; 1024];
+        while let Ok(len) = input.read(&mut bytes) {
+            if len == 0 || sender.send(bytes[..len].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    parse_chunks(&chunks, escape_timeout, send);
+}
+
+/// Parses the chunks of bytes read from the terminal into events, see `read_events`.
+fn parse_chunks<F>(chunks: &Receiver<Vec<u8>>, escape_timeout: Duration, mut send: F)
+where
+    F: FnMut(Event) -> bool,
+{
+    let mut parser = Parser::default();
+    loop {
+        let (events, done) = if parser.is_pending() {
+            match chunks.recv_timeout(escape_timeout) {
+                Ok(chunk) => (parser.feed(&chunk), false),
+                Err(RecvTimeoutError::Timeout) => (parser.flush(), false),
+                Err(RecvTimeoutError::Disconnected) => (parser.flush(), true),
+            }
+        } else {
+            match chunks.recv() {
+                Ok(chunk) => (parser.feed(&chunk), false),
+                Err(_) => return,
+            }
+        };
+        for event in events {
+            if !send(event) {
+                return;
+            }
+        }
+        if done {
+            return;
+        }
+    }
+}
+
+fn parse(bytes: &[u8]) -> Parsed {
+    let key = |code| Parsed::Complete(Some(Event::Key(Key::new(code))), 1);
+    match bytes[0] {
+        0x1b => match bytes.get(1) {
+            None => Parsed::Incomplete,
+            Some(b'[') => parse_csi(bytes),
+            Some(b'O') => match bytes.get(2) {
+                None => Parsed::Incomplete,
+                Some(byte) => {
+                    Parsed::Complete(final_byte_key(*byte, Modifiers::NONE).map(Event::Key), 3)
+                }
+            },
+            // An escape followed by another one is the Esc key, the second one starts what follows.
+            Some(0x1b) => key(KeyCode::Esc),
+            // Alt is sent as an escape before the key.
+            Some(_) => match parse(&bytes[1..]) {
+                Parsed::Complete(Some(Event::Key(mut key)), len) => {
+                    key.modifiers.alt = true;
+                    Parsed::Complete(Some(Event::Key(key)), len + 1)
+                }
+                Parsed::Complete(_, _) => key(KeyCode::Esc),
+                Parsed::Incomplete => Parsed::Incomplete,
+            },
+        },
+        b'\r' | b'\n' => key(KeyCode::Enter),
+        b'\t' => key(KeyCode::Tab),
+        0x7f => key(KeyCode::Backspace),
+        0x00 => Parsed::Complete(Some(Event::Key(Key::ctrl(' '))), 1),
+        byte @ 0x01..=0x1a => {
+            Parsed::Complete(Some(Event::Key(Key::ctrl((byte - 0x01 + b'a') as char))), 1)
+        }
+        byte @ 0x1c..=0x1f => {
+            Parsed::Complete(Some(Event::Key(Key::ctrl((byte - 0x1c + b'4') as char))), 1)
+        }
+        byte => {
+            let len = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            if bytes.len() < len {
+                return Parsed::Incomplete;
+            }
+            let event = str::from_utf8(&bytes[..len])
+                .ok()
+                .and_then(|text| text.chars().next())
+                .map(|c| Event::Key(Key::new(KeyCode::Char(c))));
+            Parsed::Complete(event, len)
+        }
+    }
+}
+
+/// Parses a sequence starting with `ESC [`.
+fn parse_csi(bytes: &[u8]) -> Parsed {
+    // X10 mouse reports are followed by three bytes of button and position.
+    if bytes.get(2) == Some(&b'M') {
+        return match bytes.len() {
+            len if len < 6 => Parsed::Incomplete,
+            _ => Parsed::Complete(parse_mouse(&bytes[..6]), 6),
+        };
+    }
+    let end = match bytes[2..]
+        .iter()
+        .position(|byte| !(0x20..=0x3f).contains(byte))
+    {
+        Some(offset) => 2 + offset,
+        None => return Parsed::Incomplete,
+    };
+    let len = end + 1;
+    let final_byte = bytes[end];
+    if !(0x40..=0x7e).contains(&final_byte) {
+        // Not a well-formed sequence, so it is skipped up to the byte that ends it.
+        return Parsed::Complete(None, end);
+    }
+    let params = &bytes[2..end];
+    let event = match (params.first(), final_byte) {
+        (Some(b'<'), b'M') | (Some(b'<'), b'm') => parse_mouse(&bytes[..len]),
+        (Some(b'?'), b'u') => Some(Event::KeyboardProtocol),
+        // Other private replies, such as the device attributes asked for along with the
+        // keyboard protocol flags.
+        (Some(b'?'), _) => None,
+        (_, b'M') => parse_mouse(&bytes[..len]),
+        _ => parse_csi_key(params, final_byte).map(Event::Key),
+    };
+    Parsed::Complete(event, len)
+}
+
+/// Parses the key of a CSI sequence, either `CSI code:shifted ; modifiers u` of the kitty
+/// keyboard protocol, or a legacy one such as `CSI 1 ; modifiers A` or `CSI 3 ~`.
+fn parse_csi_key(params: &[u8], final_byte: u8) -> Option<Key> {
+    let params: Vec<Vec<Option<u32>>> = str::from_utf8(params)
+        .ok()?
+        .split(';')
+        .map(|param| param.split(':').map(|value| value.parse().ok()).collect())
+        .collect();
+    let param = |index: usize, sub: usize| {
+        params
+            .get(index)
+            .and_then(|values| values.get(sub).copied().flatten())
+    };
+    let mut modifiers = Modifiers::from_param(param(1, 0).unwrap_or(1));
+    match final_byte {
+        b'u' => {
+            // Releases are only reported when asked for, but they are no key presses either way.
+            if param(1, 1) == Some(3) {
+                return None;
+            }
+            let mut code = match param(0, 0)? {
+                9 => KeyCode::Tab,
+                13 | 57414 => KeyCode::Enter,
+                27 => KeyCode::Esc,
+                127 => KeyCode::Backspace,
+                code @ 57399..=57408 => KeyCode::Char(char::from_digit(code - 57399, 10)?),
+                // The other keys of the private use area, such as media keys and lone modifiers.
+                57344..=63743 => return None,
+                code => KeyCode::Char(char::from_u32(code)?),
+            };
+            if modifiers.shift {
+                if let Some(shifted) = param(0, 1).and_then(char::from_u32) {
+                    code = KeyCode::Char(shifted);
+                    modifiers.shift = false;
+                }
+            }
+            Some(Key::with_modifiers(code, modifiers))
+        }
+        b'~' => {
+            let code = match param(0, 0)? {
+                1 | 7 => KeyCode::Home,
+                2 => KeyCode::Insert,
+                3 => KeyCode::Delete,
+                4 | 8 => KeyCode::End,
+                5 => KeyCode::PageUp,
+                6 => KeyCode::PageDown,
+                n @ 11..=15 => KeyCode::F(n as u8 - 10),
+                n @ 17..=21 => KeyCode::F(n as u8 - 11),
+                n @ 23..=24 => KeyCode::F(n as u8 - 12),
+                _ => return None,
+            };
+            Some(Key::with_modifiers(code, modifiers))
+        }
+        b'Z' => {
+            modifiers.shift = true;
+            Some(Key::with_modifiers(KeyCode::Tab, modifiers))
+        }
+        byte => final_byte_key(byte, modifiers),
+    }
+}
+
+/// Returns the key of the sequences that only differ in their final byte, like `CSI A` and
+/// `SS3 A` for the up arrow.
+fn final_byte_key(byte: u8, modifiers: Modifiers) -> Option<Key> {
+    let code = match byte {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P'..=b'S' => KeyCode::F(byte - b'P' + 1),
+        _ => return None,
+    };
+    Some(Key::with_modifiers(code, modifiers))
+}
+
+/// Parses a complete mouse report with termion, which knows all of their encodings.
+fn parse_mouse(sequence: &[u8]) -> Option<Event> {
+    let mut rest = sequence[1..].iter().map(|byte| Ok(*byte));
+    match termion::event::parse_event(sequence[0], &mut rest) {
+        Ok(termion::event::Event::Mouse(mouse)) => Some(Event::Mouse(mouse)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread::{sleep, spawn};
+
+    const ESCAPE_TIMEOUT: Duration = Duration::from_millis(30);
+    /// Long enough after `ESCAPE_TIMEOUT` for the timeout to have passed on a busy machine.
+    const PAUSE: Duration = Duration::from_millis(200);
+
+    /// Parses chunks of bytes that arrive after the given pauses, like keys typed on a terminal.
+    /// The chunks before the first pause are queued before parsing starts, so they arrive within
+    /// the escape timeout however busy the machine is.
+    fn events(chunks: &[(Duration, &[u8])]) -> Vec<Event> {
+        let (sender, receiver) = channel();
+        let mut chunks = chunks
+            .iter()
+            .map(|(pause, bytes)| (*pause, bytes.to_vec()))
+            .peekable();
+        while let Some((_, bytes)) = chunks.next_if(|(pause, _)| pause.is_zero()) {
+            sender.send(bytes).unwrap();
+        }
+        let chunks: Vec<(Duration, Vec<u8>)> = chunks.collect();
+        spawn(move || {
+            for (pause, bytes) in chunks {
+                sleep(pause);
+                sender.send(bytes).unwrap();
+            }
+        });
+        let mut events = vec![];
+        parse_chunks(&receiver, ESCAPE_TIMEOUT, |event| {
+            events.push(event);
+            true
+        });
+        events
+    }
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(Key::new(code))
+    }
+
+    fn alt(c: char) -> Event {
+        Event::Key(Key::with_modifiers(
+            KeyCode::Char(c),
+            Modifiers {
+                alt: true,
+                ..Modifiers::NONE
+            },
+        ))
+    }
+
+    #[test]
+    fn escape_followed_by_a_pause_is_the_esc_key() {
+        let events = events(&[(Duration::ZERO, b"\x1b"), (PAUSE, b"j")]);
+        assert_eq!(events, vec![key(KeyCode::Esc), key(KeyCode::Char('j'))]);
+    }
+
+    #[test]
+    fn escape_at_the_end_of_the_input_is_the_esc_key() {
+        let events = events(&[(Duration::ZERO, b"i"), (Duration::ZERO, b"\x1b")]);
+        assert_eq!(events, vec![key(KeyCode::Char('i')), key(KeyCode::Esc)]);
+    }
+
+    #[test]
+    fn escape_followed_by_a_key_within_the_timeout_is_alt() {
+        assert_eq!(events(&[(Duration::ZERO, b"\x1bj")]), vec![alt('j')]);
+        let events = events(&[(Duration::ZERO, b"\x1b"), (Duration::ZERO, b"j")]);
+        assert_eq!(events, vec![alt('j')]);
+    }
+
+    #[test]
+    fn sequence_split_across_reads_is_one_key() {
+        let events = events(&[(Duration::ZERO, b"\x1b["), (Duration::ZERO, b"1;5A")]);
+        let ctrl_up = Key::with_modifiers(
+            KeyCode::Up,
+            Modifiers {
+                ctrl: true,
+                ..Modifiers::NONE
+            },
+        );
+        assert_eq!(events, vec![Event::Key(ctrl_up)]);
+    }
+
+    #[test]
+    fn unfinished_sequence_is_given_up_after_the_timeout() {
+        let events = events(&[(Duration::ZERO, b"\x1b["), (PAUSE, b"A")]);
+        assert_eq!(
+            events,
+            vec![
+                key(KeyCode::Esc),
+                key(KeyCode::Char('[')),
+                key(KeyCode::Char('A'))
+            ]
+        );
+    }
+
+    #[test]
+    fn double_escape_is_two_esc_keys() {
+        let events = events(&[(Duration::ZERO, b"\x1b\x1b")]);
+        assert_eq!(events, vec![key(KeyCode::Esc), key(KeyCode::Esc)]);
+    }
+
+    #[test]
+    fn kitty_keys_are_told_apart_from_legacy_ones() {
+        let events = events(&[(Duration::ZERO, b"\t\x1b[105;5u\x1b[27u")]);
+        assert_eq!(
+            events,
+            vec![
+                key(KeyCode::Tab),
+                Event::Key(Key::ctrl('i')),
+                key(KeyCode::Esc)
+            ]
+        );
+    }
+
+    #[test]
+    fn kitty_shifted_keys_are_folded_into_the_key() {
+        let events = events(&[(Duration::ZERO, b"\x1b[108:76;6u\x1b[49:33;4u")]);
+        let ctrl_l = Key::with_modifiers(
+            KeyCode::Char('L'),
+            Modifiers {
+                ctrl: true,
+                ..Modifiers::NONE
+            },
+        );
+        assert_eq!(events, vec![Event::Key(ctrl_l), alt('!')]);
+    }
+
+    #[test]
+    fn multibyte_character_split_across_reads_is_one_key() {
+        let bytes = "é".as_bytes();
+        let events = events(&[(Duration::ZERO, &bytes[..1]), (Duration::ZERO, &bytes[1..])]);
+        assert_eq!(events, vec![key(KeyCode::Char('é'))]);
+    }
+
+    #[test]
+    fn keyboard_protocol_reply_is_reported_and_device_attributes_ignored() {
+        let events = events(&[(Duration::ZERO, b"\x1b[?1u\x1b[?62;22c")]);
+        assert_eq!(events, vec![Event::KeyboardProtocol]);
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::Path;
+use std::sync::OnceLock;
+use tree_sitter::Language;
+// Nothing but the grammars' C symbols are used from these crates, so they have to be linked
+// explicitly.
+extern crate tree_sitter_python;
+extern crate tree_sitter_rust;
+extern "C" {
+    fn tree_sitter_python() -> Language;
+    fn tree_sitter_rust() -> Language;
+}
+
+/// Comments whose text, once the comment prefix is stripped from every line, is a document in
+/// another language. Rust's `///` comments, for example, hold Markdown.
+pub struct DocComments {
+    pub node_kind: &'static str,
+    pub prefixes: &'static [&'static str],
+    pub language: &'static str,
+}
+
+/// The tokens that comments of a language are written with.
+pub struct CommentTokens {
+    /// The token that comments out the rest of a line, such as `//`.
+    pub line: Option<&'static str>,
+    /// The tokens that start and end a block comment, such as `/*` and `*/`.
+    pub block: Option<(&'static str, &'static str)>,
+}
+
+/// How the lines of a language are indented.
+#[derive(Clone, Copy)]
+pub struct Indent {
+    /// The number of columns one level of indentation is wide.
+    pub width: usize,
+    /// Whether levels are indented with tabs rather than spaces.
+    pub tabs: bool,
+}
+
+impl Indent {
+    /// Returns the text of one level of indentation.
+    pub fn unit(&self) -> String {
+        if self.tabs {
+            String::from("\t")
+        } else {
+            " ".repeat(self.width)
+        }
+    }
+}
+
+impl Default for Indent {
+    fn default() -> Indent {
+        Indent {
+            width: 4,
+            tabs: false,
+        }
+    }
+}
+
+/// `LanguageConfig` describes a language the editor supports: its grammar, the queries used to
+/// highlight it and the files it applies to.
+pub struct LanguageConfig {
+    pub name: &'static str,
+    /// File extensions of the language. They are also accepted as alternative names, such as in
+    /// the info string of a Markdown code fence.
+    pub extensions: &'static [&'static str],
+    pub language: Language,
+    pub highlights_query: &'static str,
+    pub injections_query: &'static str,
+    /// Tracks the scopes of local variables, so that uses of a variable are highlighted like its
+    /// definition.
+    pub locals_query: &'static str,
+    /// Captures text objects such as `function.inside` and `function.around`.
+    pub textobjects_query: &'static str,
+    /// Captures the nodes that can be folded. Languages without one fold by indentation.
+    pub folds_query: &'static str,
+    /// Captures the definitions listed by `:symbols`, such as `definition.function`.
+    pub tags_query: &'static str,
+    /// Captures the nodes that indent the lines inside them as `indent`, and the tokens that
+    /// close them, such as `}`, as `outdent`. Languages without one copy the indentation of the
+    /// line before.
+    pub indents_query: &'static str,
+    pub indent: Indent,
+    pub comment_tokens: CommentTokens,
+    pub doc_comments: Option<DocComments>,
+    /// Whether `'` also starts lifetimes and labels, as in Rust. It is then only paired where a
+    /// character literal can go.
+    pub lifetimes: bool,
+}
+
+/// Returns the registry of supported languages.
+pub fn languages() -> &'static [LanguageConfig] {
+    static LANGUAGES: OnceLock<Vec<LanguageConfig>> = OnceLock::new();
+    LANGUAGES.get_or_init(|| {
+        vec![
+            LanguageConfig {
+                name: "rust",
+                extensions: &["rs"],
+                language: unsafe { tree_sitter_rust() },
+                highlights_query: include_str!("../queries/rust/highlights.scm"),
+                injections_query: include_str!("../queries/rust/injections.scm"),
+                locals_query: include_str!("../queries/rust/locals.scm"),
+                textobjects_query: include_str!("../queries/rust/textobjects.scm"),
+                folds_query: include_str!("../queries/rust/folds.scm"),
+                tags_query: include_str!("../queries/rust/tags.scm"),
+                indents_query: include_str!("../queries/rust/indents.scm"),
+                indent: Indent::default(),
+                comment_tokens: CommentTokens {
+                    line: Some("//"),
+                    block: Some(("/*", "*/")),
+                },
+                doc_comments: Some(DocComments {
+                    node_kind: "line_comment",
+                    prefixes: &["///", "//!"],
+                    language: "rustdoc",
+                }),
+                lifetimes: true,
+            },
+            LanguageConfig {
+                name: "python",
+                extensions: &["py"],
+                language: unsafe { tree_sitter_python() },
+                highlights_query: include_str!("../queries/python/highlights.scm"),
+                injections_query: "",
+                locals_query: include_str!("../queries/python/locals.scm"),
+                textobjects_query: include_str!("../queries/python/textobjects.scm"),
+                folds_query: include_str!("../queries/python/folds.scm"),
+                tags_query: include_str!("../queries/python/tags.scm"),
+                indents_query: include_str!("../queries/python/indents.scm"),
+                indent: Indent::default(),
+                comment_tokens: CommentTokens {
+                    line: Some("#"),
+                    block: None,
+                },
+                doc_comments: None,
+                lifetimes: false,
+            },
+            LanguageConfig {
+                name: "markdown",
+                extensions: &["md", "markdown"],
+                language: tree_sitter_md::language(),
+                highlights_query: include_str!("../queries/markdown/highlights.scm"),
+                injections_query: include_str!("../queries/markdown/injections.scm"),
+                locals_query: "",
+                textobjects_query: "",
+                folds_query: "",
+                tags_query: "",
+                indents_query: "",
+                indent: Indent {
+                    width: 2,
+                    tabs: false,
+                },
+                comment_tokens: CommentTokens {
+                    line: None,
+                    block: Some(("<!--", "-->")),
+                },
+                doc_comments: None,
+                lifetimes: false,
+            },
+            // The Markdown of Rust doc comments, where code blocks are Rust unless they say
+            // otherwise.
+            LanguageConfig {
+                name: "rustdoc",
+                extensions: &[],
+                language: tree_sitter_md::language(),
+                highlights_query: include_str!("../queries/markdown/highlights.scm"),
+                injections_query: include_str!("../queries/rustdoc/injections.scm"),
+                locals_query: "",
+                textobjects_query: "",
+                folds_query: "",
+                tags_query: "",
+                indents_query: "",
+                indent: Indent {
+                    width: 2,
+                    tabs: false,
+                },
+                comment_tokens: CommentTokens {
+                    line: None,
+                    block: Some(("<!--", "-->")),
+                },
+                doc_comments: None,
+                lifetimes: false,
+            },
+        ]
+    })
+}
+
+/// Looks up a language by its name or one of its extensions.
+pub fn language_by_name(name: &str) -> Option<&'static LanguageConfig> {
+    let name = name.to_lowercase();
+    languages()
+        .iter()
+        .find(|config| config.name == name || config.extensions.contains(&name.as_str()))
+}
+
+pub fn language_for_path(path: &Path) -> Option<&'static LanguageConfig> {
+    let extension = path.extension()?.to_str()?;
+    languages()
+        .iter()
+        .find(|config| config.extensions.contains(&extension))
+}
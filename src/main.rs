@@ -1,8 +1,15 @@
 mod application;
-mod view;
-mod logger;
-mod highlight;
+mod color;
+mod config;
 mod display;
+mod highlight;
+mod input;
+mod language;
+mod logger;
+mod rope_regex;
+mod syntax;
+mod theme;
+mod view;
 
 use crate::application::Application;
 use crate::logger::setup_logger;
@@ -0,0 +1,435 @@
+use regex_automata::hybrid::dfa::{Cache, DFA};
+use regex_automata::hybrid::regex::{self, Regex};
+use regex_automata::hybrid::LazyStateID;
+use regex_automata::util::start;
+use regex_automata::util::syntax;
+use regex_automata::{meta, Anchored, Input};
+use ropey::Rope;
+use std::error::Error;
+use std::ops::Range;
+
+/// A regex that searches a rope chunk by chunk, without copying its text into a string.
+pub struct RopeRegex {
+    pattern: String,
+    regex: Regex,
+    /// The same regex, run on a copy of the text around a match to find its capture groups, and
+    /// to search what the DFA gives up on.
+    captures: meta::Regex,
+}
+
+impl RopeRegex {
+    /// Compiles `pattern` with `^` and `$` matching at line boundaries. Case is ignored unless
+    /// the pattern has an uppercase letter.
+    pub fn new(pattern: &str) -> Result<RopeRegex, String> {
+        let case_insensitive = !pattern.chars().any(char::is_uppercase);
+        RopeRegex::with_case(pattern, case_insensitive)
+    }
+
+    /// Compiles `pattern` like `new` does, but ignores case only when `case_insensitive` is set.
+    pub fn with_case(pattern: &str, case_insensitive: bool) -> Result<RopeRegex, String> {
+        let syntax = syntax::Config::new()
+            .multi_line(true)
+            .case_insensitive(case_insensitive);
+        let regex = Regex::builder()
+            .syntax(syntax)
+            // Unicode word boundaries are only supported as long as the text around them is ASCII.
+            .dfa(DFA::config().unicode_word_boundary(true))
+            .build(pattern)
+            .map_err(|err| error_message(&err))?;
+        let captures = meta::Regex::builder()
+            .syntax(syntax)
+            .build(pattern)
+            .map_err(|err| error_message(&err))?;
+        Ok(RopeRegex {
+            pattern: pattern.to_string(),
+            regex,
+            captures,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns the char ranges of the non-empty matches that start in the char range `range`, in
+    /// order. The text around the range is taken into account for anchors and word boundaries.
+    pub fn find_all(&self, rope: &Rope, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut cache = self.regex.create_cache();
+        let end = rope.char_to_byte(range.end);
+        let mut start = rope.char_to_byte(range.start);
+        let mut matches = vec![];
+        while start <= end {
+            let found = match find_at(&self.regex, &mut cache, rope, start, end) {
+                Ok(Some(found)) => found,
+                Ok(None) => break,
+                Err(GaveUp { at }) => {
+                    // The meta regex takes over up to the end of the line the DFA gave up on,
+                    // then the DFA goes on from there. Matches that start in that line can run
+                    // into the next one.
+                    let line = rope.byte_to_line(at);
+                    let resume = rope.line_to_byte((line + 1).min(rope.len_lines()));
+                    let window_end = rope.line_to_byte((line + 2).min(rope.len_lines())).min(end);
+                    let covered = window_end >= end;
+                    let resume_char = rope.byte_to_char(resume);
+                    for found in self.find_all_in_copy(rope, start..window_end) {
+                        if covered || found.start < resume_char {
+                            start = start.max(rope.char_to_byte(found.end));
+                            matches.push(found);
+                        }
+                    }
+                    if covered {
+                        break;
+                    }
+                    start = start.max(resume);
+                    continue;
+                }
+            };
+            if found.is_empty() {
+                // Empty matches are skipped, and the search goes on from the next character.
+                if found.start >= end {
+                    break;
+                }
+                start = rope.char_to_byte(rope.byte_to_char(found.start) + 1);
+            } else {
+                start = found.end;
+                matches.push(rope.byte_to_char(found.start)..rope.byte_to_char(found.end));
+            }
+        }
+        matches
+    }
+
+    /// Returns the char ranges of the non-empty matches that start in the byte range `range`,
+    /// searching a copy of its text with the meta regex, which handles what the DFA gives up on.
+    fn find_all_in_copy(&self, rope: &Rope, range: Range<usize>) -> Vec<Range<usize>> {
+        let (text, offset) = context(
+            rope,
+            rope.byte_to_char(range.start)..rope.byte_to_char(range.end),
+        );
+        let input = Input::new(&text).range(range.start - offset..range.end - offset);
+        self.captures
+            .find_iter(input)
+            .filter(|found| !found.is_empty())
+            .map(|found| {
+                rope.byte_to_char(found.start() + offset)..rope.byte_to_char(found.end() + offset)
+            })
+            .collect()
+    }
+
+    /// Returns the text of the capture groups of the match at the char range `range`, as found
+    /// by `find_all`, starting with the whole match. Groups that did not take part in the match
+    /// are `None`.
+    pub fn captures(&self, rope: &Rope, range: Range<usize>) -> Vec<Option<String>> {
+        let (text, offset) = context(rope, range.clone());
+        let match_range =
+            rope.char_to_byte(range.start) - offset..rope.char_to_byte(range.end) - offset;
+        // The match can end early when `find_all` stops at the end of its range, so the groups
+        // are looked for within the match only.
+        let input = Input::new(&text).range(match_range).anchored(Anchored::Yes);
+        let mut captures = self.captures.create_captures();
+        self.captures.search_captures(&input, &mut captures);
+        (0..captures.group_len())
+            .map(|index| {
+                captures
+                    .get_group(index)
+                    .map(|span| text[span.range()].to_string())
+            })
+            .collect()
+    }
+}
+
+/// Copies the lines of the char range `range` and the character before them, which is enough for
+/// anchors and word boundaries to see what they see in the rope. Returns the text with the byte
+/// offset it starts at in the rope.
+fn context(rope: &Rope, range: Range<usize>) -> (String, usize) {
+    let start = rope
+        .line_to_char(rope.char_to_line(range.start))
+        .saturating_sub(1);
+    let end_line = rope.char_to_line(range.end);
+    let end = if end_line + 1 < rope.len_lines() {
+        rope.line_to_char(end_line + 1)
+    } else {
+        rope.len_chars()
+    };
+    (rope.slice(start..end).to_string(), rope.char_to_byte(start))
+}
+
+/// Describes why a pattern failed to compile. The syntax errors at the bottom of the chain end
+/// with a line such as `error: unclosed group`, which is all that fits in the status line.
+fn error_message(err: &dyn Error) -> String {
+    match err.source() {
+        Some(source) => error_message(source),
+        None => {
+            let message = err.to_string();
+            let last = message.lines().last().unwrap_or("");
+            last.strip_prefix("error: ").unwrap_or(last).to_string()
+        }
+    }
+}
+
+/// The DFA gave up on the search at the byte `at`, which it does on Unicode word boundaries next
+/// to non-ASCII text.
+struct GaveUp {
+    at: usize,
+}
+
+/// Returns the byte range of the leftmost match that starts between the bytes `start` and `end`.
+/// The forward DFA finds where the match ends, then the reverse one where it starts.
+fn find_at(
+    regex: &Regex,
+    cache: &mut regex::Cache,
+    rope: &Rope,
+    start: usize,
+    end: usize,
+) -> Result<Option<Range<usize>>, GaveUp> {
+    let (forward_cache, reverse_cache) = cache.as_parts_mut();
+    let forward = regex.forward();
+    let look_behind = if start > 0 {
+        Some(rope.byte(start - 1))
+    } else {
+        None
+    };
+    let config = start::Config::new()
+        .anchored(Anchored::No)
+        .look_behind(look_behind);
+    let mut state = forward
+        .start_state(forward_cache, &config)
+        .map_err(|_| GaveUp { at: start })?;
+    let mut match_end = None;
+    let (chunks, mut chunk_start, _, _) = rope.chunks_at_byte(start);
+    'chunks: for chunk in chunks {
+        for (offset, byte) in chunk.bytes().enumerate() {
+            let at = chunk_start + offset;
+            if at < start {
+                continue;
+            }
+            if at >= end {
+                break 'chunks;
+            }
+            state = forward
+                .next_state(forward_cache, state, byte)
+                .map_err(|_| GaveUp { at })?;
+            // Matches are reported one byte late, so the match ended before this byte.
+            match classify(state) {
+                State::Match => match_end = Some(at),
+                State::Dead => break 'chunks,
+                State::Quit => return Err(GaveUp { at }),
+                State::Other => {}
+            }
+        }
+        chunk_start += chunk.len();
+    }
+    if !state.is_dead() {
+        state = final_state(forward, forward_cache, rope, state, end, true)?;
+        if state.is_quit() {
+            return Err(GaveUp { at: end });
+        }
+        if state.is_match() {
+            match_end = Some(end);
+        }
+    }
+    match match_end {
+        Some(end) => find_start(regex, reverse_cache, rope, start, end).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Runs the reverse DFA back from the end of a match to find where it starts.
+fn find_start(
+    regex: &Regex,
+    cache: &mut Cache,
+    rope: &Rope,
+    start: usize,
+    match_end: usize,
+) -> Result<Range<usize>, GaveUp> {
+    let reverse = regex.reverse();
+    let look_ahead = if match_end < rope.len_bytes() {
+        Some(rope.byte(match_end))
+    } else {
+        None
+    };
+    let config = start::Config::new()
+        .anchored(Anchored::Yes)
+        .look_behind(look_ahead);
+    // The forward DFA found a match ending here, so the reverse one finds its start. Giving up
+    // on the way is reported at the end of the match, where the forward search got to.
+    let mut match_start = match_end;
+    let mut state = reverse
+        .start_state(cache, &config)
+        .map_err(|_| GaveUp { at: match_end })?;
+    for at in (start..match_end).rev() {
+        state = reverse
+            .next_state(cache, state, rope.byte(at))
+            .map_err(|_| GaveUp { at: match_end })?;
+        match classify(state) {
+            State::Match => match_start = at + 1,
+            State::Dead => return Ok(match_start..match_end),
+            State::Quit => return Err(GaveUp { at: match_end }),
+            State::Other => {}
+        }
+    }
+    let state = final_state(reverse, cache, rope, state, start, false)
+        .map_err(|_| GaveUp { at: match_end })?;
+    if state.is_quit() {
+        return Err(GaveUp { at: match_end });
+    }
+    if state.is_match() {
+        match_start = start;
+    }
+    Ok(match_start..match_end)
+}
+
+enum State {
+    Match,
+    Dead,
+    Quit,
+    Other,
+}
+
+fn classify(state: LazyStateID) -> State {
+    if !state.is_tagged() {
+        State::Other
+    } else if state.is_match() {
+        State::Match
+    } else if state.is_dead() {
+        State::Dead
+    } else if state.is_quit() {
+        State::Quit
+    } else {
+        State::Other
+    }
+}
+
+/// Makes the transition past the end of the searched bytes at `at`, on the byte that follows in
+/// the direction of the search or the end of the text.
+fn final_state(
+    dfa: &DFA,
+    cache: &mut Cache,
+    rope: &Rope,
+    state: LazyStateID,
+    at: usize,
+    forward: bool,
+) -> Result<LazyStateID, GaveUp> {
+    let next_byte = if forward {
+        if at < rope.len_bytes() {
+            Some(rope.byte(at))
+        } else {
+            None
+        }
+    } else if at > 0 {
+        Some(rope.byte(at - 1))
+    } else {
+        None
+    };
+    match next_byte {
+        Some(byte) => dfa.next_state(cache, state, byte),
+        None => dfa.next_eoi_state(cache, state),
+    }
+    .map_err(|_| GaveUp { at })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the text of every match of `pattern` in `text`.
+    fn find_all(pattern: &str, text: &str) -> Vec<String> {
+        let rope = Rope::from_str(text);
+        let regex = RopeRegex::new(pattern).unwrap();
+        regex
+            .find_all(&rope, 0..rope.len_chars())
+            .into_iter()
+            .map(|range| rope.slice(range).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn word_boundaries_next_to_non_ascii_text_find_every_match() {
+        assert_eq!(
+            find_all(r"\bwörd\b", "wörd a wörd, äwörd wörd"),
+            vec!["wörd", "wörd", "wörd"]
+        );
+        assert_eq!(find_all(r"\w+\b", "ab çd"), vec!["ab", "çd"]);
+    }
+
+    #[test]
+    fn matches_are_char_ranges_in_non_ascii_text() {
+        let rope = Rope::from_str("héllo wörld, hello world");
+        let regex = RopeRegex::new("w.rld").unwrap();
+        assert_eq!(
+            regex.find_all(&rope, 0..rope.len_chars()),
+            vec![6..11, 19..24]
+        );
+    }
+
+    #[test]
+    fn only_matches_inside_the_range_are_found() {
+        let rope = Rope::from_str("ab ab ab");
+        let regex = RopeRegex::new("ab").unwrap();
+        assert_eq!(regex.find_all(&rope, 1..8), vec![3..5, 6..8]);
+        assert_eq!(regex.find_all(&rope, 3..7), vec![3..5]);
+    }
+
+    #[test]
+    fn anchors_match_at_line_boundaries() {
+        assert_eq!(find_all("^a.", "ab\nac\nbad"), vec!["ab", "ac"]);
+        assert_eq!(find_all(".d$", "ad\nbd\nde"), vec!["ad", "bd"]);
+    }
+
+    #[test]
+    fn case_is_ignored_unless_the_pattern_has_uppercase() {
+        assert_eq!(find_all("foo", "foo Foo FOO"), vec!["foo", "Foo", "FOO"]);
+        assert_eq!(find_all("Foo", "foo Foo FOO"), vec!["Foo"]);
+        let rope = Rope::from_str("foo Foo");
+        let regex = RopeRegex::with_case("foo", false).unwrap();
+        assert_eq!(regex.find_all(&rope, 0..rope.len_chars()), vec![0..3]);
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        assert_eq!(find_all("x*", "axxbx"), vec!["xx", "x"]);
+    }
+
+    #[test]
+    fn captures_hold_the_groups_of_a_match() {
+        let rope = Rope::from_str("key = välue\n");
+        let regex = RopeRegex::new(r"(\w+) = (\w+)|(z)").unwrap();
+        let found = regex.find_all(&rope, 0..rope.len_chars());
+        assert_eq!(found, vec![0..11]);
+        let groups = regex.captures(&rope, found[0].clone());
+        let expected = ["key = välue", "key", "välue"].map(|group| Some(String::from(group)));
+        assert_eq!(groups[..3], expected);
+        assert_eq!(groups[3], None);
+    }
+
+    #[test]
+    fn invalid_patterns_report_the_syntax_error() {
+        let err = RopeRegex::new("(a").err().unwrap();
+        assert_eq!(err, "unclosed group");
+    }
+
+    #[test]
+    fn matches_after_the_text_the_dfa_gives_up_on_are_found() {
+        let mut text = String::from("wörd a\n");
+        for i in 0..200 {
+            text.push_str(&format!("ab{} cd word\n", i));
+        }
+        text.push_str("wörd\nab wörd\nend");
+        let rope = Rope::from_str(&text);
+        for pattern in &[r"\bw\w*d\b", r"\b\w+\b", r"d\nab", r"\w+$", r"^\w"] {
+            let regex = RopeRegex::new(pattern).unwrap();
+            let expected: Vec<Range<usize>> = regex
+                .captures
+                .find_iter(&text)
+                .filter(|found| !found.is_empty())
+                .map(|found| rope.byte_to_char(found.start())..rope.byte_to_char(found.end()))
+                .collect();
+            assert!(expected.len() >= 200, "{}", pattern);
+            assert_eq!(
+                regex.find_all(&rope, 0..rope.len_chars()),
+                expected,
+                "{}",
+                pattern
+            );
+        }
+    }
+}
@@ -0,0 +1,674 @@
+use crate::language::{languages, LanguageConfig};
+use ropey::Rope;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::OnceLock;
+use tree_sitter::{InputEdit, Node, Parser, Query, QueryCursor, Tree};
+
+/// A definition of or a reference to a local variable, as captured by a locals query.
+struct Local {
+    range: Range<usize>,
+    definition: bool,
+}
+
+/// A definition captured by a tags query, such as a function or a struct.
+pub struct Tag {
+    /// What the definition is, such as `function`.
+    pub kind: String,
+    pub name: String,
+    /// The byte range of the whole definition.
+    pub range: Range<usize>,
+    /// The byte offset of the definition's name.
+    pub name_start: usize,
+}
+
+/// The bracket pairs that are matched, as the kinds of their opening and closing nodes.
+const BRACKETS: [(&str, &str); 4] = [("(", ")"), ("[", "]"), ("{", "}"), ("<", ">")];
+
+/// The queries of a language, compiled once and shared by every buffer in the language.
+struct Queries {
+    locals: Option<Query>,
+    textobjects: Option<Query>,
+    folds: Option<Query>,
+    tags: Option<Query>,
+    indents: Option<Query>,
+    injections: Option<Query>,
+}
+
+impl Queries {
+    fn new(language: &LanguageConfig) -> Queries {
+        Queries {
+            locals: compile_query(language, language.locals_query),
+            textobjects: compile_query(language, language.textobjects_query),
+            folds: compile_query(language, language.folds_query),
+            tags: compile_query(language, language.tags_query),
+            indents: compile_query(language, language.indents_query),
+            injections: compile_query(language, language.injections_query),
+        }
+    }
+}
+
+/// Compiles `source` for `language`, unless the language has no such query.
+fn compile_query(language: &LanguageConfig, source: &str) -> Option<Query> {
+    if source.is_empty() {
+        None
+    } else {
+        Query::new(language.language, source).ok()
+    }
+}
+
+/// Returns the queries of `language`, compiling them the first time they are needed.
+fn queries(language: &LanguageConfig) -> &'static Queries {
+    static QUERIES: OnceLock<Vec<OnceLock<Queries>>> = OnceLock::new();
+    let queries = QUERIES.get_or_init(|| languages().iter().map(|_| OnceLock::new()).collect());
+    let index = languages()
+        .iter()
+        .position(|config| config.name == language.name)
+        .expect("languages are registered");
+    queries[index].get_or_init(|| Queries::new(language))
+}
+
+/// Parses `text`, reusing the parts of `old_tree` that were not edited.
+fn parse(parser: &mut Parser, text: &Rope, old_tree: Option<&Tree>) -> Tree {
+    parser
+        .parse_with(
+            &mut |byte, _| {
+                if byte >= text.len_bytes() {
+                    return &[][..];
+                }
+                let (chunk, chunk_start, _, _) = text.chunk_at_byte(byte);
+                &chunk.as_bytes()[byte - chunk_start..]
+            },
+            old_tree,
+        )
+        .unwrap()
+}
+
+/// Returns the bytes of `range` in `text`. They are borrowed from the rope, and only copied when
+/// they span several of its chunks.
+fn text_bytes(text: &Rope, range: Range<usize>) -> Cow<'_, [u8]> {
+    let (chunk, chunk_start, _, _) = text.chunk_at_byte(range.start);
+    let chunk = &chunk.as_bytes()[range.start - chunk_start..];
+    if range.len() <= chunk.len() {
+        Cow::Borrowed(&chunk[..range.len()])
+    } else {
+        Cow::Owned(text.bytes_at(range.start).take(range.len()).collect())
+    }
+}
+
+/// `Syntax` holds the tree-sitter syntax tree of a buffer. The tree is kept in step with the
+/// edits made to the buffer so that it can be reparsed incrementally.
+pub struct Syntax {
+    tree: Tree,
+    parser: Parser,
+    queries: &'static Queries,
+}
+
+impl Syntax {
+    pub fn new(language: &LanguageConfig, text: &Rope) -> Syntax {
+        let mut parser = Parser::new();
+        parser.set_language(language.language).unwrap();
+        let tree = parse(&mut parser, text, None);
+        Syntax {
+            tree,
+            parser,
+            queries: queries(language),
+        }
+    }
+
+    /// Adjusts the tree to an edit of the text. The tree stays stale until the next `update`.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        self.tree.edit(edit);
+    }
+
+    /// Reparses the edited text, reusing the parts of the tree the edits did not touch.
+    pub fn update(&mut self, text: &Rope) {
+        self.tree = parse(&mut self.parser, text, Some(&self.tree));
+    }
+
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Returns the range of the smallest named node that encloses `range` and is larger than it.
+    pub fn expand(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let mut node = self
+            .tree
+            .root_node()
+            .named_descendant_for_byte_range(range.start, range.end)?;
+        while node.byte_range() == range || !node.is_named() {
+            node = node.parent()?;
+        }
+        Some(node.byte_range())
+    }
+
+    /// Returns the range of the first named child of the smallest named node that encloses
+    /// `range`.
+    pub fn shrink(&self, range: Range<usize>) -> Option<Range<usize>> {
+        let node = self
+            .tree
+            .root_node()
+            .named_descendant_for_byte_range(range.start, range.end)?;
+        node.named_child(0).map(|child| child.byte_range())
+    }
+
+    /// Returns the range of the next or previous named sibling of the node that `range` covers.
+    pub fn sibling(&self, range: Range<usize>, forward: bool) -> Option<Range<usize>> {
+        let mut node = self
+            .tree
+            .root_node()
+            .named_descendant_for_byte_range(range.start, range.end)?;
+        // A range that spans several siblings continues from the last or first of them.
+        if node.byte_range() != range {
+            let mut cursor = node.walk();
+            let edge = node.named_children(&mut cursor).find(|child| {
+                if forward {
+                    child.end_byte() == range.end
+                } else {
+                    child.start_byte() == range.start
+                }
+            });
+            if let Some(edge) = edge {
+                node = edge;
+            }
+        }
+        // Nodes that span the same text as their parent have no siblings of their own, so the
+        // outermost of them is used.
+        while let Some(parent) = node.parent() {
+            if parent.byte_range() != node.byte_range() {
+                break;
+            }
+            node = parent;
+        }
+        let sibling = if forward {
+            node.next_named_sibling()
+        } else {
+            node.prev_named_sibling()
+        };
+        sibling.map(|sibling| sibling.byte_range())
+    }
+
+    /// Returns the byte ranges of the text objects called `name`, such as `function.inside`, in
+    /// document order. Objects that are captured as several nodes span all of them.
+    pub fn textobjects(&self, text: &Rope, name: &str) -> Vec<Range<usize>> {
+        let query = match &self.queries.textobjects {
+            Some(query) => query,
+            None => return vec![],
+        };
+        let capture_names = query.capture_names();
+
+        let mut objects = vec![];
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, self.tree.root_node(), |node| {
+            text_bytes(text, node.byte_range())
+        });
+        for query_match in matches {
+            let object = query_match
+                .captures
+                .iter()
+                .filter(|capture| capture_names[capture.index as usize] == name)
+                .map(|capture| capture.node.byte_range())
+                .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end));
+            if let Some(object) = object {
+                objects.push(object);
+            }
+        }
+        objects.sort_by_key(|object| (object.start, object.end));
+        objects.dedup();
+        objects
+    }
+
+    /// Returns the first and last line of every node that can be folded, or `None` if the
+    /// language has no folds query.
+    pub fn folds(&self, text: &Rope) -> Option<Vec<(usize, usize)>> {
+        let query = self.queries.folds.as_ref()?;
+        let mut folds = vec![];
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, self.tree.root_node(), |node| {
+            text_bytes(text, node.byte_range())
+        });
+        for query_match in matches {
+            for capture in query_match.captures {
+                let start = capture.node.start_position();
+                let end = capture.node.end_position();
+                // A node that ends at the start of a line does not cover that line.
+                let end_row = if end.column == 0 && end.row > start.row {
+                    end.row - 1
+                } else {
+                    end.row
+                };
+                if end_row > start.row {
+                    folds.push((start.row, end_row));
+                }
+            }
+        }
+        Some(folds)
+    }
+
+    /// Returns the definitions in the tree, in document order. Definitions without a `name`
+    /// capture are named after their text up to the first line break or brace.
+    pub fn tags(&self, text: &Rope) -> Vec<Tag> {
+        let query = match &self.queries.tags {
+            Some(query) => query,
+            None => return vec![],
+        };
+        let capture_names = query.capture_names();
+
+        let mut tags = vec![];
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, self.tree.root_node(), |node| {
+            text_bytes(text, node.byte_range())
+        });
+        for query_match in matches {
+            let mut definition = None;
+            let mut name = None;
+            for capture in query_match.captures {
+                let capture_name = &capture_names[capture.index as usize];
+                if let Some(kind) = capture_name.strip_prefix("definition.") {
+                    definition = Some((kind, capture.node));
+                } else if capture_name == "name" {
+                    name = Some(capture.node);
+                }
+            }
+            let (kind, node) = match definition {
+                Some(definition) => definition,
+                None => continue,
+            };
+            let (name, name_start) = match name {
+                Some(name) => (text_bytes(text, name.byte_range()), name.start_byte()),
+                None => {
+                    let line = text
+                        .bytes_at(node.start_byte())
+                        .take(node.end_byte() - node.start_byte())
+                        .take_while(|b| *b != b'\n' && *b != b'{')
+                        .collect();
+                    (Cow::Owned(line), node.start_byte())
+                }
+            };
+            let name = String::from_utf8_lossy(&name);
+            tags.push((
+                query_match.pattern_index,
+                Tag {
+                    kind: kind.to_string(),
+                    name: name.trim_end().to_string(),
+                    range: node.byte_range(),
+                    name_start,
+                },
+            ));
+        }
+
+        // A definition can be captured by several patterns, such as a method that is also a
+        // function. The first pattern wins.
+        tags.sort_by_key(|(pattern, tag)| (tag.range.start, Reverse(tag.range.end), *pattern));
+        tags.dedup_by(|(_, a), (_, b)| a.range == b.range);
+        tags.into_iter().map(|(_, tag)| tag).collect()
+    }
+
+    /// Returns how many levels a line that starts at `byte` is indented, or `None` if the
+    /// language has no indents query.
+    ///
+    /// Every node captured as `indent` that encloses the last character before the line adds a
+    /// level, unless it was already closed, as a block is by its `}`. Nodes that start on the same
+    /// line add a single level between them. A line that starts with a token captured as
+    /// `outdent` is one level shallower.
+    pub fn indent_level(&self, text: &Rope, byte: usize) -> Option<usize> {
+        let query = self.queries.indents.as_ref()?;
+        let capture_names = query.capture_names();
+
+        let mut indents = HashSet::new();
+        let mut outdents = HashSet::new();
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, self.tree.root_node(), |node| {
+            text_bytes(text, node.byte_range())
+        });
+        for query_match in matches {
+            for capture in query_match.captures {
+                match capture_names[capture.index as usize].as_str() {
+                    "indent" => indents.insert(capture.node.id()),
+                    "outdent" => outdents.insert(capture.node.id()),
+                    _ => false,
+                };
+            }
+        }
+
+        let root = self.tree.root_node();
+        let mut level: usize = 0;
+        let mut before = text.bytes_at(byte);
+        let anchor = std::iter::from_fn(|| before.prev())
+            .position(|b| !b.is_ascii_whitespace())
+            .map(|offset| byte - offset - 1);
+        if let Some(anchor) = anchor {
+            let closed = |node: Node| {
+                node.end_byte() <= byte
+                    && node
+                        .child(node.child_count().saturating_sub(1))
+                        .is_some_and(|last| outdents.contains(&last.id()) && !last.is_missing())
+            };
+            let mut last_row = None;
+            let mut node = root.descendant_for_byte_range(anchor, anchor + 1);
+            while let Some(current) = node {
+                let row = current.start_position().row;
+                if indents.contains(&current.id()) && !closed(current) && last_row != Some(row) {
+                    level += 1;
+                    last_row = Some(row);
+                }
+                node = current.parent();
+            }
+        }
+
+        let first = text
+            .bytes_at(byte)
+            .position(|b| b != b' ' && b != b'\t')
+            .map(|offset| byte + offset)
+            .filter(|first| text.byte(*first) != b'\n');
+        if let Some(first) = first {
+            let token = root.descendant_for_byte_range(first, first + 1);
+            if token.is_some_and(|token| outdents.contains(&token.id()) && !token.is_missing()) {
+                level = level.saturating_sub(1);
+            }
+        }
+        Some(level)
+    }
+
+    /// Returns the name of the language injected at `byte`, such as the language of a Markdown
+    /// code block. Nested injections win over the ones around them.
+    pub fn injected_language(&self, text: &Rope, byte: usize) -> Option<String> {
+        let query = self.queries.injections.as_ref()?;
+        let capture_names = query.capture_names();
+
+        let mut innermost: Option<(String, Range<usize>)> = None;
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, self.tree.root_node(), |node| {
+            text_bytes(text, node.byte_range())
+        });
+        for query_match in matches {
+            let mut language = query
+                .property_settings(query_match.pattern_index)
+                .iter()
+                .find(|property| &*property.key == "injection.language")
+                .and_then(|property| property.value.as_deref())
+                .map(String::from);
+            let mut content = None;
+            for capture in query_match.captures {
+                let range = capture.node.byte_range();
+                match capture_names[capture.index as usize].as_str() {
+                    "injection.language" => {
+                        language =
+                            Some(String::from_utf8_lossy(&text_bytes(text, range)).to_string())
+                    }
+                    "injection.content" => content = Some(range),
+                    _ => {}
+                }
+            }
+            if let (Some(language), Some(content)) = (language, content) {
+                let inside = content.start <= byte && byte < content.end;
+                let smaller = innermost
+                    .as_ref()
+                    .is_none_or(|(_, other)| content.len() < other.len());
+                if inside && smaller {
+                    innermost = Some((language, content));
+                }
+            }
+        }
+        innermost.map(|(language, _)| language)
+    }
+
+    /// Returns whether `byte` is inside a string or a comment. The end of a line comment counts
+    /// as inside it.
+    pub fn in_string_or_comment(&self, byte: usize) -> bool {
+        let mut node = self
+            .tree
+            .root_node()
+            .descendant_for_byte_range(byte.saturating_sub(1), byte);
+        while let Some(current) = node {
+            let kind = current.kind();
+            let comment = kind.contains("comment");
+            if (comment || kind.contains("string") || kind == "char_literal")
+                && current.start_byte() < byte
+                && (byte < current.end_byte() || (comment && kind != "block_comment"))
+            {
+                return true;
+            }
+            node = current.parent();
+        }
+        false
+    }
+
+    /// Returns whether the character at `byte` closes a literal, such as the quote at the end of
+    /// a string.
+    pub fn closes_literal(&self, byte: usize) -> bool {
+        let mut node = self
+            .tree
+            .root_node()
+            .descendant_for_byte_range(byte, byte + 1);
+        while let Some(current) = node {
+            if current.end_byte() != byte + 1 {
+                return false;
+            }
+            if current.is_named() && current.start_byte() < byte {
+                return true;
+            }
+            node = current.parent();
+        }
+        false
+    }
+
+    /// Returns the byte offset of the bracket that matches the one at `byte`. Only brackets that
+    /// are tokens of the tree are matched, so ones inside strings and comments never are.
+    pub fn matching_bracket(&self, byte: usize) -> Option<usize> {
+        let node = self
+            .tree
+            .root_node()
+            .descendant_for_byte_range(byte, byte + 1)?;
+        if node.is_named() || node.start_byte() != byte {
+            return None;
+        }
+        bracket_pairs(node.parent()?)
+            .into_iter()
+            .find_map(|(open, close)| {
+                if open == node {
+                    Some(close.start_byte())
+                } else if close == node {
+                    Some(open.start_byte())
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Returns the byte offsets of the opening and closing brackets of every pair that is at
+    /// least partly inside `range`, with how many pairs enclose it.
+    pub fn brackets(&self, range: Range<usize>) -> Vec<(usize, usize, usize)> {
+        let mut brackets = vec![];
+        let mut stack = vec![(self.tree.root_node(), 0)];
+        while let Some((node, depth)) = stack.pop() {
+            let pairs = bracket_pairs(node);
+            for (open, close) in &pairs {
+                if open.start_byte() < range.end && range.start < close.end_byte() {
+                    brackets.push((open.start_byte(), close.start_byte(), depth));
+                }
+            }
+            let depth = if pairs.is_empty() { depth } else { depth + 1 };
+            let mut cursor = node.walk();
+            for child in node.children(&mut cursor) {
+                if child.start_byte() < range.end && range.start < child.end_byte() {
+                    stack.push((child, depth));
+                }
+            }
+        }
+        brackets
+    }
+
+    /// Returns the byte ranges of the `ERROR` and `MISSING` nodes in the tree, in document order.
+    pub fn errors(&self) -> Vec<Range<usize>> {
+        let mut errors = vec![];
+        let mut cursor = self.tree.walk();
+        loop {
+            let node = cursor.node();
+            let descend = if node.is_error() || node.is_missing() {
+                errors.push(node.start_byte()..node.end_byte());
+                false
+            } else {
+                node.has_error()
+            };
+
+            if descend && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    return errors;
+                }
+            }
+        }
+    }
+
+    /// Returns the byte ranges of every occurrence of the symbol at `byte`, in document order.
+    ///
+    /// Occurrences are resolved to their definition the same way the highlighter resolves local
+    /// variables: by looking for the closest earlier definition of the name in the enclosing
+    /// scopes. Symbols without a local definition, such as functions and types, match every other
+    /// occurrence of their name that has no local definition either.
+    pub fn references(&self, text: &Rope, byte: usize) -> Vec<Range<usize>> {
+        let query = match &self.queries.locals {
+            Some(query) => query,
+            None => return vec![],
+        };
+        let capture_names = query.capture_names();
+
+        // The whole tree is the outermost scope.
+        let mut scopes = vec![self.tree.root_node().byte_range()];
+        let mut locals = vec![];
+        let mut cursor = QueryCursor::new();
+        let matches = cursor.matches(query, self.tree.root_node(), |node| {
+            text_bytes(text, node.byte_range())
+        });
+        for query_match in matches {
+            for capture in query_match.captures {
+                let range = capture.node.byte_range();
+                match capture_names[capture.index as usize].as_str() {
+                    "local.scope" => scopes.push(range),
+                    "local.definition" => locals.push(Local {
+                        range,
+                        definition: true,
+                    }),
+                    "local.reference" => locals.push(Local {
+                        range,
+                        definition: false,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+
+        // Definitions are also captured as references, so only the definition is kept.
+        locals.sort_by_key(|local| (local.range.start, local.range.end, !local.definition));
+        locals.dedup_by(|a, b| a.range == b.range);
+
+        let target = match locals
+            .iter()
+            .find(|local| local.range.start <= byte && byte < local.range.end)
+        {
+            Some(local) => local,
+            None => return vec![],
+        };
+        let name = text_bytes(text, target.range.clone());
+        let definition = resolve(target, &locals, &scopes, text);
+
+        locals
+            .iter()
+            .filter(|local| text_bytes(text, local.range.clone()) == name)
+            .filter(|local| resolve(local, &locals, &scopes, text) == definition)
+            .map(|local| local.range.clone())
+            .collect()
+    }
+}
+
+/// Returns the pairs of brackets among the children of `node`, matched by nesting.
+fn bracket_pairs(node: Node) -> Vec<(Node, Node)> {
+    let mut pairs = vec![];
+    let mut open: Vec<Node> = vec![];
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.is_named() {
+            continue;
+        }
+        if BRACKETS.iter().any(|(opening, _)| *opening == child.kind()) {
+            open.push(child);
+        } else if let Some(&(opening, _)) = BRACKETS
+            .iter()
+            .find(|(_, closing)| *closing == child.kind())
+        {
+            if open.last().map(|node| node.kind()) == Some(opening) {
+                pairs.push((open.pop().unwrap(), child));
+            }
+        }
+    }
+    pairs
+}
+
+/// Returns the innermost scope that contains `range`.
+fn innermost_scope<'a>(range: &Range<usize>, scopes: &'a [Range<usize>]) -> &'a Range<usize> {
+    scopes
+        .iter()
+        .filter(|scope| scope.start <= range.start && range.end <= scope.end)
+        .min_by_key(|scope| scope.end - scope.start)
+        .unwrap()
+}
+
+/// Returns the range of the definition that `local` refers to, if it has a local definition.
+fn resolve(
+    local: &Local,
+    locals: &[Local],
+    scopes: &[Range<usize>],
+    text: &Rope,
+) -> Option<Range<usize>> {
+    if local.definition {
+        return Some(local.range.clone());
+    }
+    let name = text_bytes(text, local.range.clone());
+
+    let mut enclosing: Vec<&Range<usize>> = scopes
+        .iter()
+        .filter(|scope| scope.start <= local.range.start && local.range.end <= scope.end)
+        .collect();
+    enclosing.sort_by_key(|scope| scope.end - scope.start);
+    enclosing.into_iter().find_map(|scope| {
+        locals
+            .iter()
+            .rev()
+            .filter(|other| other.definition && other.range.start <= local.range.start)
+            .filter(|other| text_bytes(text, other.range.clone()) == name)
+            .find(|other| innermost_scope(&other.range, scopes) == scope)
+            .map(|other| other.range.clone())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_bytes_match_the_text_across_chunks() {
+        let source = "fn wörld() { let x = \"ünïcode\"; }\n".repeat(200);
+        let text = Rope::from_str(&source);
+        assert!(text.chunks().count() > 1);
+        let mut offset = 0;
+        for chunk in text.chunks() {
+            offset += chunk.len();
+            if offset >= source.len() {
+                break;
+            }
+            let boundary = text.byte_to_char(offset);
+            let start = text.char_to_byte(boundary - 2);
+            let end = text.char_to_byte(boundary + 2);
+            let bytes = text_bytes(&text, start..end);
+            assert!(matches!(bytes, Cow::Owned(_)));
+            assert_eq!(&*bytes, &source.as_bytes()[start..end]);
+        }
+        assert!(matches!(text_bytes(&text, 0..2), Cow::Borrowed(b"fn")));
+        assert_eq!(&*text_bytes(&text, source.len()..source.len()), b"");
+    }
+}
@@ -0,0 +1,479 @@
+use crate::color::{Color, ColorSupport};
+use crate::config::runtime_dirs;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use toml::Value;
+
+const DEFAULT_THEME: &str = include_str!("../themes/default.toml");
+
+/// The themes built into the editor, by name. Themes in the runtime directories take precedence.
+const SHIPPED_THEMES: &[(&str, &str)] = &[
+    ("default", DEFAULT_THEME),
+    ("gruvbox", include_str!("../themes/gruvbox.toml")),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Modifier {
+    Bold,
+    Dim,
+    Italic,
+    Underline,
+    Undercurl,
+    Reversed,
+}
+
+impl Modifier {
+    fn from_name(name: &str) -> Option<Modifier> {
+        match name {
+            "bold" => Some(Modifier::Bold),
+            "dim" => Some(Modifier::Dim),
+            "italic" => Some(Modifier::Italic),
+            "underline" => Some(Modifier::Underline),
+            "undercurl" => Some(Modifier::Undercurl),
+            "reversed" => Some(Modifier::Reversed),
+            _ => None,
+        }
+    }
+
+    fn escape(&self) -> &'static str {
+        match self {
+            Modifier::Bold => "\x1b[1m",
+            Modifier::Dim => "\x1b[2m",
+            Modifier::Italic => "\x1b[3m",
+            Modifier::Underline => "\x1b[4m",
+            Modifier::Undercurl => "\x1b[4:3m",
+            Modifier::Reversed => "\x1b[7m",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub modifiers: Vec<Modifier>,
+}
+
+impl Style {
+    pub fn fg_string(&self, support: ColorSupport) -> Option<String> {
+        self.fg.map(|color| color.fg_string(support))
+    }
+
+    pub fn bg_string(&self, support: ColorSupport) -> Option<String> {
+        self.bg.map(|color| color.bg_string(support))
+    }
+
+    /// Returns the escape sequences for the style's modifiers, or `None` if it has none.
+    pub fn attrs_string(&self) -> Option<String> {
+        if self.modifiers.is_empty() {
+            None
+        } else {
+            Some(self.modifiers.iter().map(|m| m.escape()).collect())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ThemeError {
+    NotFound(String),
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ThemeError::NotFound(name) => write!(f, "theme not found: {}", name),
+            ThemeError::Io(err) => write!(f, "could not read theme: {}", err),
+            ThemeError::Parse(err) => write!(f, "could not parse theme: {}", err),
+            ThemeError::Invalid(reason) => write!(f, "invalid theme: {}", reason),
+        }
+    }
+}
+
+/// A `Theme` maps highlight scopes such as `keyword` and UI elements such as `statusline` to
+/// styles. Themes are TOML files in a `themes` directory:
+///
+/// ```toml
+/// rainbow = ["yellow", "magenta", "blue"]
+///
+/// [palette]
+/// blue = "#61afef"
+///
+/// [scopes]
+/// keyword = "blue"
+/// comment = { fg = "#7f848e", modifiers = ["italic"] }
+///
+/// [ui]
+/// selection = { bg = "#3e4451" }
+/// ```
+///
+/// Colors are either `#rrggbb` values, 256-color palette indexes, names of the 16 ANSI colors or
+/// names defined in the `palette` table. The optional `rainbow` list holds the styles that
+/// nested brackets rotate through.
+pub struct Theme {
+    scopes: HashMap<String, Style>,
+    ui: HashMap<String, Style>,
+    rainbow: Vec<Style>,
+}
+
+impl Theme {
+    pub fn load(name: &str) -> Result<Theme, ThemeError> {
+        for dir in runtime_dirs() {
+            let path = dir.join("themes").join(format!("{}.toml", name));
+            if path.is_file() {
+                let source = fs::read_to_string(path).map_err(ThemeError::Io)?;
+                return Theme::parse(&source);
+            }
+        }
+        match SHIPPED_THEMES.iter().find(|(shipped, _)| *shipped == name) {
+            Some((_, source)) => Theme::parse(source),
+            None => Err(ThemeError::NotFound(String::from(name))),
+        }
+    }
+
+    /// Returns the names of all themes that can be loaded.
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = SHIPPED_THEMES
+            .iter()
+            .map(|(name, _)| String::from(*name))
+            .collect();
+        for dir in runtime_dirs() {
+            if let Ok(entries) = fs::read_dir(dir.join("themes")) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if path.extension().is_some_and(|ext| ext == "toml") {
+                        if let Some(stem) = path.file_stem() {
+                            names.push(stem.to_string_lossy().to_string());
+                        }
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn parse(source: &str) -> Result<Theme, ThemeError> {
+        let value: Value = toml::from_str(source).map_err(ThemeError::Parse)?;
+        if let Some(table) = value.as_table() {
+            check_keys(table, &["rainbow", "palette", "scopes", "ui"])?;
+        }
+
+        let mut palette = HashMap::new();
+        if let Some(table) = value.get("palette").and_then(Value::as_table) {
+            for (key, color) in table {
+                palette.insert(key.clone(), parse_color(color, &HashMap::new())?);
+            }
+        }
+
+        let parse_table = |key: &str| -> Result<HashMap<String, Style>, ThemeError> {
+            let mut styles = HashMap::new();
+            if let Some(table) = value.get(key).and_then(Value::as_table) {
+                for (name, style) in table {
+                    styles.insert(name.clone(), parse_style(style, &palette)?);
+                }
+            }
+            Ok(styles)
+        };
+
+        let mut rainbow = vec![];
+        if let Some(styles) = value.get("rainbow").and_then(Value::as_array) {
+            for style in styles {
+                rainbow.push(parse_style(style, &palette)?);
+            }
+        }
+
+        Ok(Theme {
+            scopes: parse_table("scopes")?,
+            ui: parse_table("ui")?,
+            rainbow,
+        })
+    }
+
+    /// Returns the style of a highlight scope. Scopes without a style of their own inherit the
+    /// style of their closest parent, so `function.method.call` falls back to `function.method`
+    /// and then to `function`.
+    pub fn scope(&self, scope: &str) -> Option<&Style> {
+        resolve(&self.scopes, scope)
+    }
+
+    /// Returns the style of a UI element, or an empty style if the theme does not define one.
+    /// Like scopes, elements fall back to their parents: `gutter.error` to `gutter`.
+    pub fn ui(&self, element: &str) -> Style {
+        resolve(&self.ui, element).cloned().unwrap_or_default()
+    }
+
+    /// Returns the style of brackets nested `depth` levels deep, or `None` if the theme has no
+    /// rainbow colors.
+    pub fn rainbow(&self, depth: usize) -> Option<&Style> {
+        if self.rainbow.is_empty() {
+            None
+        } else {
+            Some(&self.rainbow[depth % self.rainbow.len()])
+        }
+    }
+}
+
+impl Default for Theme {
+    /// Loads the default theme, falling back to the copy built into the binary if the one on
+    /// disk is broken.
+    fn default() -> Theme {
+        Theme::load("default")
+            .or_else(|_| Theme::parse(DEFAULT_THEME))
+            .unwrap()
+    }
+}
+
+/// Looks up the style of a dotted name, walking up to its parents until one is found.
+fn resolve<'a>(styles: &'a HashMap<String, Style>, name: &str) -> Option<&'a Style> {
+    let mut name = name;
+    loop {
+        if let Some(style) = styles.get(name) {
+            return Some(style);
+        }
+        match name.rfind('.') {
+            Some(i) => name = &name[..i],
+            None => return None,
+        }
+    }
+}
+
+/// Rejects the keys of `table` that are not in `known`, so that typos are not silently ignored.
+fn check_keys(table: &toml::value::Table, known: &[&str]) -> Result<(), ThemeError> {
+    match table.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(ThemeError::Invalid(format!("unknown key: {}", key))),
+        None => Ok(()),
+    }
+}
+
+fn parse_style(value: &Value, palette: &HashMap<String, Color>) -> Result<Style, ThemeError> {
+    match value {
+        Value::Table(table) => {
+            check_keys(table, &["fg", "bg", "modifiers"])?;
+            let mut style = Style::default();
+            if let Some(fg) = table.get("fg") {
+                style.fg = Some(parse_color(fg, palette)?);
+            }
+            if let Some(bg) = table.get("bg") {
+                style.bg = Some(parse_color(bg, palette)?);
+            }
+            if let Some(modifiers) = table.get("modifiers").and_then(Value::as_array) {
+                for modifier in modifiers {
+                    let name = modifier.as_str().unwrap_or_default();
+                    style
+                        .modifiers
+                        .push(Modifier::from_name(name).ok_or_else(|| {
+                            ThemeError::Invalid(format!("unknown modifier: {}", modifier))
+                        })?);
+                }
+            }
+            Ok(style)
+        }
+        _ => Ok(Style {
+            fg: Some(parse_color(value, palette)?),
+            ..Style::default()
+        }),
+    }
+}
+
+fn parse_color(value: &Value, palette: &HashMap<String, Color>) -> Result<Color, ThemeError> {
+    let invalid = || ThemeError::Invalid(format!("unknown color: {}", value));
+    match value {
+        Value::Integer(n) if (0..=255).contains(n) => Ok(Color::Ansi(*n as u8)),
+        Value::String(s) if s.starts_with('#') => {
+            let digits = &s.as_bytes()[1..];
+            if digits.len() != 6 || !digits.iter().all(u8::is_ascii_hexdigit) {
+                return Err(invalid());
+            }
+            let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| invalid());
+            Ok(Color::Rgb(channel(1)?, channel(3)?, channel(5)?))
+        }
+        Value::String(s) => {
+            if let Some(color) = palette.get(s) {
+                return Ok(*color);
+            }
+            let ansi = match s.as_str() {
+                "reset" => return Ok(Color::Reset),
+                "black" => 0,
+                "red" => 1,
+                "green" => 2,
+                "yellow" => 3,
+                "blue" => 4,
+                "magenta" => 5,
+                "cyan" => 6,
+                "white" => 7,
+                "light-black" | "gray" => 8,
+                "light-red" => 9,
+                "light-green" => 10,
+                "light-yellow" => 11,
+                "light-blue" => 12,
+                "light-magenta" => 13,
+                "light-cyan" => 14,
+                "light-white" => 15,
+                _ => return Err(invalid()),
+            };
+            Ok(Color::Ansi(ansi))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(value: &str) -> Result<Color, ThemeError> {
+        let mut palette = HashMap::new();
+        palette.insert(String::from("accent"), Color::Rgb(1, 2, 3));
+        palette.insert(String::from("red"), Color::Ansi(160));
+        parse_color(&Value::String(String::from(value)), &palette)
+    }
+
+    fn error(source: &str) -> String {
+        Theme::parse(source).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn colors_are_hex_palette_or_ansi_names() {
+        assert_eq!(color("#61afEF").unwrap(), Color::Rgb(0x61, 0xaf, 0xef));
+        assert_eq!(color("accent").unwrap(), Color::Rgb(1, 2, 3));
+        assert_eq!(color("light-cyan").unwrap(), Color::Ansi(14));
+        assert_eq!(color("reset").unwrap(), Color::Reset);
+        let index = parse_color(&Value::Integer(208), &HashMap::new()).unwrap();
+        assert_eq!(index, Color::Ansi(208));
+    }
+
+    #[test]
+    fn palette_entries_take_precedence_over_ansi_names() {
+        assert_eq!(color("red").unwrap(), Color::Ansi(160));
+    }
+
+    #[test]
+    fn invalid_colors_are_rejected() {
+        for value in &[
+            "#61afe",
+            "#61afef0",
+            "#61afeg",
+            "#+1afef",
+            "#éééé",
+            "#",
+            "blurple",
+        ] {
+            assert!(color(value).is_err(), "{}", value);
+        }
+        assert!(parse_color(&Value::Integer(256), &HashMap::new()).is_err());
+        assert!(parse_color(&Value::Integer(-1), &HashMap::new()).is_err());
+        assert!(parse_color(&Value::Boolean(true), &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn styles_use_the_palette_of_the_theme() {
+        let theme = Theme::parse(
+            r##"
+            rainbow = ["accent", "green"]
+
+            [palette]
+            accent = "#112233"
+
+            [scopes]
+            keyword = "accent"
+            comment = { fg = "accent", bg = 0, modifiers = ["italic", "bold"] }
+
+            [ui]
+            selection = { bg = "accent" }
+            "##,
+        )
+        .unwrap();
+        let accent = Some(Color::Rgb(0x11, 0x22, 0x33));
+        assert_eq!(theme.scope("keyword").unwrap().fg, accent);
+        let comment = theme.scope("comment").unwrap();
+        assert_eq!(comment.bg, Some(Color::Ansi(0)));
+        assert_eq!(comment.modifiers, [Modifier::Italic, Modifier::Bold]);
+        assert_eq!(theme.ui("selection").bg, accent);
+        assert_eq!(theme.rainbow(2).unwrap().fg, accent);
+    }
+
+    #[test]
+    fn palette_entries_cannot_refer_to_each_other() {
+        let source = "[palette]\nbase = \"#000000\"\nfg = \"base\"";
+        assert_eq!(error(source), "invalid theme: unknown color: \"base\"");
+    }
+
+    #[test]
+    fn unknown_keys_and_modifiers_are_rejected() {
+        assert_eq!(
+            error("[colors]\nred = 1"),
+            "invalid theme: unknown key: colors"
+        );
+        assert_eq!(
+            error("[scopes]\nkeyword = { foreground = \"red\" }"),
+            "invalid theme: unknown key: foreground"
+        );
+        assert_eq!(
+            error("[ui]\nstatusline = { modifiers = [\"blink\"] }"),
+            "invalid theme: unknown modifier: \"blink\""
+        );
+    }
+
+    fn styles(names: &[&str]) -> HashMap<String, Style> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let style = Style {
+                    fg: Some(Color::Ansi(i as u8)),
+                    ..Style::default()
+                };
+                (name.to_string(), style)
+            })
+            .collect()
+    }
+
+    fn resolved(styles: &HashMap<String, Style>, name: &str) -> Option<Color> {
+        resolve(styles, name).and_then(|style| style.fg)
+    }
+
+    #[test]
+    fn scopes_fall_back_to_their_closest_parent() {
+        let styles = styles(&["keyword", "keyword.control", "function.method"]);
+        assert_eq!(resolved(&styles, "keyword"), Some(Color::Ansi(0)));
+        assert_eq!(resolved(&styles, "keyword.control"), Some(Color::Ansi(1)));
+        assert_eq!(
+            resolved(&styles, "keyword.control.import"),
+            Some(Color::Ansi(1))
+        );
+        assert_eq!(resolved(&styles, "keyword.operator"), Some(Color::Ansi(0)));
+        assert_eq!(
+            resolved(&styles, "function.method.call"),
+            Some(Color::Ansi(2))
+        );
+    }
+
+    #[test]
+    fn scopes_without_a_styled_parent_have_no_style() {
+        let styles = styles(&["keyword.control", "string"]);
+        assert_eq!(resolved(&styles, "keyword"), None);
+        assert_eq!(resolved(&styles, "function.method"), None);
+        assert_eq!(resolved(&styles, "stringy"), None);
+        assert_eq!(resolved(&styles, ""), None);
+    }
+
+    #[test]
+    fn ui_elements_fall_back_to_their_parent_or_an_empty_style() {
+        let theme = Theme::parse("[ui]\ngutter = \"red\"").unwrap();
+        assert_eq!(theme.ui("gutter.error").fg, Some(Color::Ansi(1)));
+        assert_eq!(theme.ui("statusline"), Style::default());
+    }
+
+    #[test]
+    fn shipped_themes_parse() {
+        for (name, source) in SHIPPED_THEMES {
+            assert!(Theme::parse(source).is_ok(), "{}", name);
+        }
+    }
+}
@@ -1,39 +1,143 @@
 use super::traits::View as ViewTrait;
-use crate::application::buffer::Buffer;
+use crate::application::buffer::{Buffer, Fold, Symbol};
 use crate::application::modes::Modes;
+use crate::color::ColorSupport;
+use crate::config::Config;
 use crate::display::{Display, Point, Rect};
 use crate::highlight::Highlighter;
-use ropey::Rope;
+use crate::input::KEYBOARD_PROTOCOL_FLAGS;
+use crate::theme::{Style, Theme};
 use std::io::{stdout, BufWriter, Stdout, Write};
+use std::ops::Range;
 use termion;
+use termion::input::MouseTerminal;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::AlternateScreen;
 
-fn char_idxs_to_points(
-    start: usize,
-    end: usize,
-    offset: usize,
-    content: ropey::RopeSlice,
-) -> (Point, Point) {
-    let start_idx = start - offset;
-    let end_idx = end - offset;
-
-    let start_row = content.char_to_line(start_idx);
-    let end_row = content.char_to_line(end_idx);
-
-    let start_col = start_idx - content.line_to_char(start_row);
-    let end_col = end_idx - content.line_to_char(end_row);
-
-    (
-        Point {
-            row: start_row,
-            col: start_col,
-        },
-        Point {
-            row: end_row,
-            col: end_col,
-        },
-    )
+/// Returns the buffer lines that are shown on screen, one per row, starting at `top`. Lines
+/// hidden by closed folds are skipped.
+fn visible_lines(buffer: &Buffer, top: usize, height: usize) -> Vec<usize> {
+    let mut lines = vec![top];
+    while lines.len() < height {
+        match buffer.visible_line_after(lines[lines.len() - 1]) {
+            Some(line) => lines.push(line),
+            None => break,
+        }
+    }
+    lines
+}
+
+/// Splits the characters between `start` and `end` into one segment per row they are shown on.
+/// Characters on lines that are not shown are left out.
+fn segments(buffer: &Buffer, lines: &[usize], start: usize, end: usize) -> Vec<(Point, Point)> {
+    lines
+        .iter()
+        .enumerate()
+        .filter_map(|(row, line)| {
+            let line_start = buffer.data.line_to_char(*line);
+            let line_end = line_start + buffer.data.line(*line).len_chars();
+            let segment_start = start.max(line_start);
+            let segment_end = end.min(line_end);
+            if segment_start < segment_end {
+                Some((
+                    Point {
+                        row,
+                        col: segment_start - line_start,
+                    },
+                    Point {
+                        row,
+                        col: segment_end - line_start,
+                    },
+                ))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the first lines of the folds that enclose the line shown below them, outermost first
+/// and at most `depth` of them.
+fn context_lines(buffer: &Buffer, lines: &[usize], depth: usize) -> Vec<usize> {
+    let mut context: Vec<usize> = vec![];
+    // Every pinned line hides a row, which can change the line the context is for, so this is
+    // repeated until it settles.
+    for _ in 0..=depth {
+        let line = match lines.get(context.len()) {
+            Some(line) => *line,
+            None => break,
+        };
+        let mut enclosing: Vec<usize> = buffer
+            .folds
+            .iter()
+            .filter(|fold| fold.start < line && line <= fold.end)
+            .map(|fold| fold.start)
+            .collect();
+        enclosing.dedup();
+        if enclosing.len() > depth {
+            enclosing.drain(..enclosing.len() - depth);
+        }
+        if enclosing == context {
+            break;
+        }
+        context = enclosing;
+    }
+    context
+}
+
+/// The text shown after the first line of a closed fold.
+fn fold_summary(fold: &Fold) -> String {
+    format!(" ⋯ {} lines ", fold.end - fold.start)
+}
+
+/// How many items of a picker are shown at once.
+const PICKER_HEIGHT: usize = 10;
+
+/// The widest the outline panel gets.
+const OUTLINE_WIDTH: usize = 32;
+
+/// What is shown of a search being typed.
+struct SearchView {
+    prompt: String,
+    query: String,
+    matches: Vec<Range<usize>>,
+    current: Option<usize>,
+}
+
+/// What is shown of an open picker.
+struct PickerView {
+    title: String,
+    query: String,
+    items: Vec<String>,
+    selected: usize,
+}
+
+/// The positions of an opening and a closing bracket, with how deeply the pair is nested.
+type BracketPair = (usize, usize, usize);
+
+/// A value derived from the buffer, kept from one render to the next until what it was derived
+/// from changes.
+struct Cached<K, V> {
+    key: Option<K>,
+    value: V,
+}
+
+impl<K: PartialEq, V: Clone + Default> Cached<K, V> {
+    fn new() -> Cached<K, V> {
+        Cached {
+            key: None,
+            value: V::default(),
+        }
+    }
+
+    /// Returns the value for `key`, computing it only if the key changed.
+    fn get(&mut self, key: K, compute: impl FnOnce() -> V) -> V {
+        if self.key.as_ref() != Some(&key) {
+            self.value = compute();
+            self.key = Some(key);
+        }
+        self.value.clone()
+    }
 }
 
 #[derive(Clone)]
@@ -44,21 +148,44 @@ struct CursorPosition {
 
 pub struct Terminal {
     top: usize,
-    output: BufWriter<RawTerminal<AlternateScreen<Stdout>>>,
+    output: BufWriter<MouseTerminal<RawTerminal<AlternateScreen<Stdout>>>>,
     last_position: CursorPosition,
     position: CursorPosition,
     pub command: String,
     mode: Modes,
     highlighter: Highlighter,
-    processed_buffer: Rope,
+    color_support: ColorSupport,
+    theme: Theme,
+    message: String,
+    popup: Vec<String>,
+    pending_keys: String,
+    picker: Option<PickerView>,
+    search: Option<SearchView>,
+    outline: bool,
+    sticky_context: usize,
+    rainbow_brackets: bool,
+    /// Whether the kitty keyboard protocol flags were pushed, and have to be popped on exit.
+    keyboard_protocol: bool,
+    /// The buffer line shown on each row by the last render.
+    rows: Vec<usize>,
+    gutter_width: usize,
+    /// The occurrences of the symbol under the cursor, by buffer version and cursor position.
+    references: Cached<(usize, usize), Vec<Range<usize>>>,
+    /// The syntax errors, by buffer version.
+    errors: Cached<usize, Vec<Range<usize>>>,
+    /// The brackets on screen, by buffer version and the range of characters shown.
+    brackets: Cached<(usize, Range<usize>), Vec<BracketPair>>,
+    /// The symbols listed in the outline, by buffer version.
+    symbols: Cached<usize, Vec<Symbol>>,
 }
 
 impl Terminal {
-    pub fn new() -> Terminal {
+    pub fn new(config: &Config) -> Terminal {
+        let color_support = ColorSupport::detect();
         return Terminal {
             output: BufWriter::with_capacity(
                 1_048_576,
-                AlternateScreen::from(stdout()).into_raw_mode().unwrap(),
+                MouseTerminal::from(AlternateScreen::from(stdout()).into_raw_mode().unwrap()),
             ),
             top: 0,
             // TODO(jenterkin): `last_position` should probably be optional
@@ -67,8 +194,158 @@ impl Terminal {
             command: String::from(""),
             mode: Modes::Normal,
             highlighter: Highlighter::new(),
-            processed_buffer: Rope::from(""),
+            color_support,
+            theme: Theme::default(),
+            message: String::from(""),
+            popup: vec![],
+            pending_keys: String::from(""),
+            picker: None,
+            search: None,
+            outline: false,
+            sticky_context: config.sticky_context,
+            rainbow_brackets: config.rainbow_brackets,
+            keyboard_protocol: false,
+            rows: vec![],
+            gutter_width: 0,
+            references: Cached::new(),
+            errors: Cached::new(),
+            brackets: Cached::new(),
+            symbols: Cached::new(),
+        };
+    }
+
+    /// Returns the row that `line` is shown on, if it is on screen.
+    fn row_of(&self, buffer: &Buffer, line: usize) -> Option<usize> {
+        visible_lines(buffer, self.top, self.text_height())
+            .iter()
+            .position(|visible| *visible == line)
+    }
+
+    /// The number of rows available for buffer content. The last two rows hold the status line
+    /// and the command line.
+    fn text_height(&self) -> usize {
+        termion::terminal_size().unwrap().1 as usize - 2
+    }
+
+    fn paint(&self, display: &mut Display, start: Point, end: Point, style: &Style) {
+        if let Some(attrs) = style.attrs_string() {
+            display.decorate(start, end, &attrs);
+        }
+        display.highlight(
+            start,
+            end,
+            style.fg_string(self.color_support).as_ref(),
+            style.bg_string(self.color_support).as_ref(),
+        );
+    }
+
+    fn put_styled(&self, display: &mut Display, row: usize, col: usize, text: &str, style: &Style) {
+        display.put_str(
+            row,
+            col,
+            text,
+            style.fg_string(self.color_support).as_ref(),
+            style.bg_string(self.color_support).as_ref(),
+        );
+    }
+
+    fn render_status_line(&self, display: &mut Display, row: usize, width: usize, buffer: &Buffer) {
+        let left = format!(" {} ", self.mode.name());
+        let selections = match buffer.selections.len() {
+            1 => String::from(""),
+            count => format!("{} selections  ", count),
         };
+        let right = format!(
+            " {}{}{}:{} ",
+            self.pending_keys,
+            selections,
+            buffer.get_root_selection_line() + 1,
+            buffer.get_root_selection_col() + 1
+        );
+        let padding = width.saturating_sub(left.len() + right.len());
+        let line = format!("{}{}{}", left, " ".repeat(padding), right);
+        self.put_styled(display, row, 0, &line, &self.theme.ui("statusline"));
+    }
+
+    /// Draws the popup lines in a box above the status line, cut off at the top of the screen.
+    fn render_popup(&self, display: &mut Display, bottom: usize) {
+        let style = self.theme.ui("popup");
+        let width = self
+            .popup
+            .iter()
+            .map(|l| l.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 2;
+        let top = bottom.saturating_sub(self.popup.len());
+        for (i, line) in self.popup.iter().take(bottom).enumerate() {
+            let text = format!(" {:<width$}", line, width = width - 1);
+            self.put_styled(display, top + i, 0, &text, &style);
+        }
+    }
+
+    /// Draws the items of the picker in a box above the status line, scrolled so that the
+    /// highlighted one is visible.
+    fn render_picker(
+        &self,
+        display: &mut Display,
+        picker: &PickerView,
+        bottom: usize,
+        width: usize,
+    ) {
+        let style = self.theme.ui("popup");
+        let selected_style = self.theme.ui("popup.selected");
+        let height = picker.items.len().min(PICKER_HEIGHT).min(bottom);
+        let first = (picker.selected + 1).saturating_sub(height);
+        let top = bottom - height;
+        for (i, item) in picker.items.iter().enumerate().skip(first).take(height) {
+            let text = format!(" {:<w$}", item, w = width - 1);
+            let style = if i == picker.selected {
+                &selected_style
+            } else {
+                &style
+            };
+            self.put_styled(display, top + i - first, 0, &text, style);
+        }
+    }
+
+    /// Draws the symbols of the buffer in a panel on the right, highlighting the innermost one
+    /// the primary selection is in.
+    fn render_outline(
+        &mut self,
+        display: &mut Display,
+        buffer: &Buffer,
+        height: usize,
+        width: usize,
+    ) {
+        let style = self.theme.ui("outline");
+        let current_style = self.theme.ui("outline.current");
+        let panel_width = OUTLINE_WIDTH.min(width / 3);
+        let left = width - panel_width;
+
+        let symbols = self.symbols.get(buffer.version, || buffer.symbols());
+        let position = buffer.get_root_selection().start;
+        let current = symbols
+            .iter()
+            .rposition(|symbol| symbol.range.start <= position && position < symbol.range.end);
+        let first = current.map_or(0, |current| current.saturating_sub(height / 2));
+        for row in 0..height {
+            let i = first + row;
+            let text = match symbols.get(i) {
+                Some(symbol) => format!("│{}{}", "  ".repeat(symbol.depth), symbol.name),
+                None => String::from("│"),
+            };
+            let text: String = format!("{:<w$}", text, w = panel_width)
+                .chars()
+                .take(panel_width)
+                .collect();
+            let style = if Some(i) == current {
+                &current_style
+            } else {
+                &style
+            };
+            self.put_styled(display, row, left, &text, style);
+        }
     }
 
     fn update_position(&mut self, row: u16, col: u16) {
@@ -87,6 +364,10 @@ impl ViewTrait for Terminal {
     fn start(&mut self) {
         self.update_position(self.position.col, self.position.row);
         write!(self.output, "{}", termion::cursor::Hide).unwrap();
+        // Terminals that support the kitty keyboard protocol answer the query for its flags
+        // before the device attributes, which every terminal answers.
+        write!(self.output, "\x1b[?u\x1b[c").unwrap();
+        self.output.flush().unwrap();
     }
 
     fn change_mode(&mut self, mode: Modes) {
@@ -108,10 +389,10 @@ impl ViewTrait for Terminal {
                 self.update_position(last_row, 1);
                 self.position.col += 1;
                 self.write_char(':');
-                self.mode = Modes::Command
             }
             _ => {}
         }
+        self.mode = mode;
     }
 
     fn write_char(&mut self, char: char) {
@@ -129,107 +410,355 @@ impl ViewTrait for Terminal {
     }
 
     fn scroll_up(&mut self, buffer: &mut Buffer) {
-        if self.top > 0 {
-            let height = termion::terminal_size().unwrap().1 as usize;
-            if buffer.get_root_selection_line() >= self.top + height - 2 {
+        if let Some(top) = buffer.visible_line_before(self.top) {
+            let bottom_row = visible_lines(buffer, self.top, self.text_height()).len() - 1;
+            if self.row_of(buffer, buffer.get_root_selection_line()) >= Some(bottom_row) {
                 buffer.select_char_up();
             }
-            self.top -= 1;
+            self.top = top;
         }
     }
 
     fn scroll_down(&mut self, buffer: &mut Buffer) {
-        // why is it `- 2`?
-        if self.top < buffer.len_lines() - 2 {
+        if let Some(top) = buffer.visible_line_after(self.top) {
             if buffer.get_root_selection_line() <= self.top {
                 buffer.select_char_down();
             }
-            self.top += 1;
+            self.top = top;
         }
     }
 
     fn render(&mut self, buffer: &Buffer, command: &String) {
         let (width, height) = termion::terminal_size().unwrap();
+        let text_height = self.text_height();
 
-        let selection_line = buffer.get_root_selection_line();
-        if selection_line > self.top + height as usize - 2 {
-            self.top += 1;
-        } else if selection_line < self.top {
-            self.top -= 1;
+        let selection_line = buffer.visible_line(buffer.get_root_selection_line());
+        if self.top >= buffer.data.len_lines() || selection_line < self.top {
+            self.top = selection_line;
         }
-
-        if self.processed_buffer.len_chars() == 0 {
-            self.processed_buffer = buffer.data.clone();
+        self.top = buffer.visible_line(self.top);
+        if self.row_of(buffer, selection_line).is_none() {
+            // Scroll just far enough for the selection to be on the last row.
+            let mut top = selection_line;
+            for _ in 1..text_height {
+                match buffer.visible_line_before(top) {
+                    Some(line) => top = line,
+                    None => break,
+                }
+            }
+            self.top = top;
         }
 
-        let start_line_idx = self.processed_buffer.line_to_char(self.top);
-        let end_line_idx = self
-            .processed_buffer
-            .line_to_char(self.top + height as usize);
-        let visible_content = self.processed_buffer.slice(start_line_idx..end_line_idx);
+        let lines = visible_lines(buffer, self.top, text_height);
+        // The signature lines of the scopes around the top of the view are pinned over its first
+        // rows, as long as they do not hide the selection.
+        let cursor_row = self.row_of(buffer, selection_line).unwrap_or(0);
+        let context = context_lines(buffer, &lines, self.sticky_context.min(cursor_row));
+        let lines: Vec<usize> = context
+            .iter()
+            .chain(&lines[context.len()..])
+            .copied()
+            .collect();
+        let closed_folds: Vec<Option<&Fold>> = lines
+            .iter()
+            .map(|line| {
+                buffer
+                    .folds
+                    .iter()
+                    .find(|fold| fold.closed && fold.start == *line)
+            })
+            .collect();
+        let mut content = String::from("");
+        for (line, fold) in lines.iter().zip(&closed_folds) {
+            let text = buffer.data.line(*line).to_string();
+            content.push_str(text.trim_end_matches('\n'));
+            if let Some(fold) = fold {
+                content.push_str(&fold_summary(fold));
+            }
+            content.push('\n');
+        }
+        let start_line_idx = buffer.data.line_to_char(lines[0]);
+        let end_line_idx = buffer.data.line_to_char(lines[lines.len() - 1] + 1);
 
+        // The gutter holds a sign column followed by the line numbers.
+        let gutter_width = buffer.data.len_lines().to_string().len() + 2;
+        self.rows = lines.clone();
+        self.gutter_width = gutter_width;
         let mut display = Display::new(
             Rect {
                 height: height - 1,
                 width: width,
             },
-            &visible_content.to_string(),
+            gutter_width,
+            &content,
         );
 
-        let highlights = self.highlighter.get_highlights(
-            &self.processed_buffer.to_string(),
-            start_line_idx,
-            end_line_idx,
+        self.paint(
+            &mut display,
+            Point {
+                row: cursor_row,
+                col: 0,
+            },
+            Point {
+                row: cursor_row,
+                col: width as usize,
+            },
+            &self.theme.ui("cursorline"),
         );
+
+        let highlights =
+            self.highlighter
+                .get_highlights(buffer, start_line_idx, end_line_idx, &self.theme);
         for highlight in highlights {
-            let (start_point, end_point) = char_idxs_to_points(
-                highlight.start,
-                highlight.end,
-                start_line_idx,
-                visible_content,
-            );
-            display.highlight(start_point, end_point, Some(&highlight.color), None);
-        }
-
-        for selection in &buffer.selections {
-            let (start_point, end_point) = char_idxs_to_points(
-                selection.start,
-                selection.end,
-                start_line_idx,
-                visible_content,
-            );
-            display.highlight(
-                start_point,
-                end_point,
-                None,
-                Some(&termion::color::LightBlack.bg_str().to_string()),
-            );
+            for (start_point, end_point) in segments(buffer, &lines, highlight.start, highlight.end)
+            {
+                self.paint(&mut display, start_point, end_point, &highlight.style);
+            }
+        }
+
+        if self.rainbow_brackets {
+            let range = start_line_idx..end_line_idx;
+            let brackets = self
+                .brackets
+                .get((buffer.version, range.clone()), || buffer.brackets(range));
+            for (open, close, depth) in brackets {
+                if let Some(style) = self.theme.rainbow(depth) {
+                    for bracket in [open, close] {
+                        for (start_point, end_point) in
+                            segments(buffer, &lines, bracket, bracket + 1)
+                        {
+                            self.paint(&mut display, start_point, end_point, style);
+                        }
+                    }
+                }
+            }
         }
 
+        let context_style = self.theme.ui("context");
+        for row in 0..context.len() {
+            let start = Point { row, col: 0 };
+            let end = Point {
+                row,
+                col: width as usize,
+            };
+            self.paint(&mut display, start, end, &context_style);
+        }
+
+        let reference_style = self.theme.ui("reference");
+        let cursor = buffer.get_root_selection().start;
+        let references = self.references.get((buffer.version, cursor), || {
+            buffer.references_under_cursor()
+        });
+        for reference in references {
+            for (start_point, end_point) in segments(buffer, &lines, reference.start, reference.end)
+            {
+                self.paint(&mut display, start_point, end_point, &reference_style);
+            }
+        }
+
+        if let Some((bracket, other)) = buffer.matching_brackets() {
+            let bracket_style = self.theme.ui("bracket.match");
+            for bracket in [bracket, other] {
+                for (start_point, end_point) in segments(buffer, &lines, bracket, bracket + 1) {
+                    self.paint(&mut display, start_point, end_point, &bracket_style);
+                }
+            }
+        }
+
+        if let Some(search) = &self.search {
+            let match_style = self.theme.ui("search.match");
+            for m in search
+                .matches
+                .iter()
+                .filter(|m| m.start < end_line_idx && m.end > start_line_idx)
+            {
+                for (start_point, end_point) in segments(buffer, &lines, m.start, m.end) {
+                    self.paint(&mut display, start_point, end_point, &match_style);
+                }
+            }
+        }
+
+        let errors = self.errors.get(buffer.version, || buffer.syntax_errors());
+        let error_style = self.theme.ui("error");
+        let mut error_rows = vec![];
+        for error in &errors {
+            for (start_point, end_point) in segments(buffer, &lines, error.start, error.end) {
+                error_rows.push(start_point.row);
+                self.paint(&mut display, start_point, end_point, &error_style);
+            }
+        }
+
+        for (i, selection) in buffer.selections.iter().enumerate() {
+            let style = if i == 0 {
+                self.theme.ui("selection.primary")
+            } else {
+                self.theme.ui("selection")
+            };
+            for (start_point, end_point) in segments(buffer, &lines, selection.start, selection.end)
+            {
+                self.paint(&mut display, start_point, end_point, &style);
+            }
+        }
+
+        let fold_style = self.theme.ui("fold");
+        for (row, (line, fold)) in lines.iter().zip(&closed_folds).enumerate() {
+            if let Some(fold) = fold {
+                let text = buffer.data.line(*line).to_string();
+                let col = text.trim_end_matches('\n').chars().count();
+                let len = fold_summary(fold).chars().count();
+                let start = Point { row, col };
+                let end = Point {
+                    row,
+                    col: col + len,
+                };
+                self.paint(&mut display, start, end, &fold_style);
+            }
+        }
+
+        let gutter_style = self.theme.ui("gutter");
+        let gutter_error_style = self.theme.ui("gutter.error");
+        for (row, (line, fold)) in lines.iter().zip(&closed_folds).enumerate() {
+            let number = format!("{:>w$} ", line + 1, w = gutter_width - 2);
+            self.put_styled(&mut display, row, 1, &number, &gutter_style);
+            if error_rows.contains(&row) {
+                self.put_styled(&mut display, row, 0, "●", &gutter_error_style);
+            } else if fold.is_some() {
+                self.put_styled(&mut display, row, 0, "▸", &gutter_style);
+            } else {
+                self.put_styled(&mut display, row, 0, " ", &gutter_style);
+            }
+        }
+
+        if self.outline {
+            self.render_outline(&mut display, buffer, text_height, width as usize);
+        }
+        self.render_status_line(&mut display, text_height, width as usize, buffer);
+        if !self.popup.is_empty() {
+            self.render_popup(&mut display, text_height);
+        }
+        if let Some(picker) = &self.picker {
+            self.render_picker(&mut display, picker, text_height, width as usize);
+        }
+
+        write!(self.output, "{}", display.rendered()).unwrap();
+
+        let last_line = height;
         write!(
             self.output,
             "{}{}",
-            termion::cursor::Goto(1, 1),
-            display.rendered()
+            termion::cursor::Goto(1, last_line),
+            termion::clear::CurrentLine
         )
         .unwrap();
-        self.output.flush().unwrap();
-
         match self.mode {
             Modes::Command => {
-                let last_line = termion::terminal_size().unwrap().1;
                 self.position.row = last_line;
-                write!(
-                    self.output,
-                    "{}:{}",
-                    termion::cursor::Goto(1, last_line),
-                    &command
-                )
-                .unwrap();
+                write!(self.output, ":{}", &command).unwrap();
+            }
+            Modes::Picker => {
+                if let Some(picker) = &self.picker {
+                    write!(self.output, "{}> {}", picker.title, picker.query).unwrap();
+                }
+            }
+            Modes::Search => {
+                if let Some(search) = &self.search {
+                    write!(self.output, "{}{}", search.prompt, search.query).unwrap();
+                    if let Some(current) = search.current {
+                        write!(self.output, "  [{}/{}]", current + 1, search.matches.len())
+                            .unwrap();
+                    }
+                }
+            }
+            _ => {
+                write!(self.output, "{}", &self.message).unwrap();
             }
-            _ => {}
         }
 
         self.output.flush().unwrap();
     }
+
+    fn position_at(&self, buffer: &Buffer, row: usize, col: usize) -> Option<usize> {
+        let line = *self.rows.get(row)?;
+        let line_start = buffer.data.line_to_char(line);
+        let line_len = buffer.data.line(line).len_chars();
+        let col = col.saturating_sub(self.gutter_width);
+        Some(line_start + col.min(line_len.saturating_sub(1)))
+    }
+
+    fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    fn show_message(&mut self, message: String) {
+        self.message = message;
+    }
+
+    fn show_popup(&mut self, lines: Vec<String>) {
+        self.popup = lines;
+    }
+
+    fn clear_messages(&mut self) {
+        self.message = String::from("");
+        self.popup = vec![];
+    }
+
+    fn enable_keyboard_protocol(&mut self) {
+        if !self.keyboard_protocol {
+            write!(self.output, "\x1b[>{}u", KEYBOARD_PROTOCOL_FLAGS).unwrap();
+            self.keyboard_protocol = true;
+        }
+    }
+
+    fn show_pending_keys(&mut self, keys: &str) {
+        self.pending_keys = if keys.is_empty() {
+            String::from("")
+        } else {
+            format!("{}   ", keys)
+        };
+    }
+
+    fn show_picker(&mut self, title: &str, query: &str, items: Vec<String>, selected: usize) {
+        self.picker = Some(PickerView {
+            title: String::from(title),
+            query: String::from(query),
+            items,
+            selected,
+        });
+    }
+
+    fn hide_picker(&mut self) {
+        self.picker = None;
+    }
+
+    fn show_search(
+        &mut self,
+        prompt: &str,
+        query: &str,
+        matches: Vec<Range<usize>>,
+        current: Option<usize>,
+    ) {
+        self.search = Some(SearchView {
+            prompt: String::from(prompt),
+            query: String::from(query),
+            matches,
+            current,
+        });
+    }
+
+    fn hide_search(&mut self) {
+        self.search = None;
+    }
+
+    fn toggle_outline(&mut self) {
+        self.outline = !self.outline;
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.keyboard_protocol {
+            write!(self.output, "\x1b[<u").unwrap();
+            self.output.flush().unwrap();
+        }
+    }
 }
@@ -1,5 +1,7 @@
 use crate::application::buffer::Buffer;
 use crate::application::modes::Modes;
+use crate::theme::Theme;
+use std::ops::Range;
 
 pub trait View {
     fn start(&mut self);
@@ -8,4 +10,28 @@ pub trait View {
     fn scroll_up(&mut self, buffer: &mut Buffer);
     fn scroll_down(&mut self, buffer: &mut Buffer);
     fn render(&mut self, data: &Buffer, command: &String);
+    /// Returns the position in the buffer that is drawn at a row and column of the screen.
+    fn position_at(&self, buffer: &Buffer, row: usize, col: usize) -> Option<usize>;
+    fn set_theme(&mut self, theme: Theme);
+    fn show_message(&mut self, message: String);
+    fn show_popup(&mut self, lines: Vec<String>);
+    fn clear_messages(&mut self);
+    /// Shows the keys of a command that is still being typed, such as a count.
+    fn show_pending_keys(&mut self, keys: &str);
+    /// Shows a picker with the given query and matching items, `selected` being highlighted.
+    fn show_picker(&mut self, title: &str, query: &str, items: Vec<String>, selected: usize);
+    fn hide_picker(&mut self);
+    /// Shows the pattern being typed after its prompt, such as `/` for a search, highlighting
+    /// its matches and telling which one is `current`.
+    fn show_search(
+        &mut self,
+        prompt: &str,
+        query: &str,
+        matches: Vec<Range<usize>>,
+        current: Option<usize>,
+    );
+    fn hide_search(&mut self);
+    fn toggle_outline(&mut self);
+    /// Turns on the kitty keyboard protocol once the terminal said it supports it.
+    fn enable_keyboard_protocol(&mut self);
 }